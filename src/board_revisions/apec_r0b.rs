@@ -4,7 +4,24 @@ use embassy_rp::i2c::Config;
 use embassy_rp::peripherals::{PWM_CH4, PWM_CH6, PWM_CH7};
 use embassy_rp::pwm::Pwm;
 use crate::board_revisions::IOExpanderBoardIO;
-use crate::Irqs;
+use crate::firmware::Irqs;
+
+/// Number of cascaded 74HC595s on the board
+pub const SHIFT_REGISTER_STAGES: usize = 2;
+
+/// Three electrode water level probe on the FDC1004
+pub mod level_probe {
+    use crate::iox::analog_input::fdc1004::Channel;
+    use crate::iox::analog_input::fdc1004::level::LevelElectrodes;
+
+    pub const ELECTRODES: LevelElectrodes = LevelElectrodes {
+        level: Channel::CIN4,
+        reference: Channel::CIN3,
+        environmental: Channel::CIN2,
+    };
+    /// Distance between the empty and full calibration points
+    pub const HEIGHT_MM: f32 = 200f32;
+}
 
 pub mod shift_register_positions {
     pub const CN1_3V3: usize = 0;
@@ -0,0 +1,22 @@
+//! Host tests link against the defmt features of embassy and the crate's own Format impls,
+//! without defmt-rtt there is nothing providing the logger symbols
+
+#[defmt::global_logger]
+struct DiscardLogger;
+
+unsafe impl defmt::Logger for DiscardLogger {
+    fn acquire() {}
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {}
+
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+defmt::timestamp!("{=u64}", 0);
+
+#[defmt::panic_handler]
+fn panic() -> ! {
+    panic!("defmt panic")
+}
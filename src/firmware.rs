@@ -0,0 +1,233 @@
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::gpio::{AnyPin, Level, Output, Pin};
+use embassy_rp::bind_interrupts;
+use embassy_rp::peripherals::{PWM_CH4, PWM_CH6, USB};
+use embassy_rp::pwm::{Channel, Pwm};
+use embassy_rp::usb::{Driver, self};
+use embassy_time::{Duration, Timer};
+use embassy_rp::i2c::{self, Async, Config};
+use embassy_rp::peripherals::I2C0;
+use embedded_hal_async::i2c::I2c;
+use log::log;
+use {defmt_rtt as _, panic_probe as _};
+use iox::analog_input::ads1115::ADS111x;
+use crate::iox::analog_input::ads1115::{ADS111xConfig, DataRate, InputMultiplexer, ProgramableGainAmplifier};
+use crate::iox::analog_input::ads1115::scan::{ScanEntry, ScanSequencer, ScanTable};
+use crate::iox::analog_input::fdc1004;
+use crate::iox::analog_input::fdc1004::OutputRate;
+use crate::iox::analog_input::fdc1004::level::{LevelSensor, PublishedLevel};
+use crate::iox::analog_output::PwmSlice;
+use crate::iox::binary_output::c595_chain::C595Chain;
+use crate::iox::binary_output::{OutputEnable, ShiftRegister};
+use crate::board_revisions::apec_r0b::{level_probe, SHIFT_REGISTER_STAGES};
+use libm::logf;
+use crate::{board_revisions, iox};
+
+bind_interrupts!(pub(crate) struct Irqs {
+    USBCTRL_IRQ => usb::InterruptHandler<USB>;
+    I2C0_IRQ => i2c::InterruptHandler<I2C0>;
+});
+
+#[embassy_executor::task]
+async fn logger_task(driver: Driver<'static, USB>) {
+    embassy_usb_logger::run!(1024, log::LevelFilter::Info, driver);
+}
+
+const SCAN_VCC: usize = 0;
+const SCAN_V_R_CN5: usize = 1;
+const SCAN_V_R_CN6: usize = 2;
+
+static ADS_SCAN_TABLE: ScanTable<3> = ScanTable::new();
+static LEVEL: PublishedLevel = PublishedLevel::new();
+/// Output enable of the shift register chain, any task can call disable_all on it
+static SR_OUTPUT_ENABLE: OutputEnable<'static> = OutputEnable::new();
+
+#[embassy_executor::task]
+async fn i2c_task(mut i2c: i2c::I2c<'static, I2C0, Async>) {
+    let ads1115_1 = ADS111x::new(
+        0x48,
+        ADS111xConfig::default().pga(ProgramableGainAmplifier::V6_144)
+    ).unwrap();
+    let ads1115_2 = ADS111x::new(
+        0x49,
+        ADS111xConfig::default().pga(ProgramableGainAmplifier::V6_144)
+    ).unwrap();
+    let mut ads = [ads1115_1, ads1115_2];
+    let mut scan = ScanSequencer::new([
+        ScanEntry::new(1, InputMultiplexer::AIN0GND, ProgramableGainAmplifier::V6_144, DataRate::SPS128),
+        ScanEntry::new(0, InputMultiplexer::AIN0AIN1, ProgramableGainAmplifier::V6_144, DataRate::SPS128),
+        ScanEntry::new(0, InputMultiplexer::AIN2AIN3, ProgramableGainAmplifier::V6_144, DataRate::SPS128),
+    ], &ADS_SCAN_TABLE);
+    let mut fdc1004 = iox::analog_input::fdc1004::FDC1004::new(0x50, OutputRate::SPS100);
+    let fdc1004_present = match fdc1004.init(&mut i2c).await {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("FDC1004 not found, capacitance readings disabled: {:?}", e);
+            false
+        }
+    };
+    // Calibration points are loaded or captured at runtime through LEVEL.request
+    let mut level = LevelSensor::new(level_probe::ELECTRODES, level_probe::HEIGHT_MM).alarm(10f32, 20f32);
+
+    loop {
+        let failed = scan.scan_all(&mut i2c, &mut ads).await;
+        if failed > 0 {
+            log::warn!("{:?} ADS111x scan entries failed", failed);
+        }
+
+        let max_age = Duration::from_secs(5);
+
+        let vcc = ADS_SCAN_TABLE.latest_voltage(SCAN_VCC, max_age);
+        log::info!("Vcc: {:?}", vcc);
+
+        let v_r_cn5 = ADS_SCAN_TABLE.latest_voltage(SCAN_V_R_CN5, max_age);
+        log::info!("V_R_CN5: {:?}", v_r_cn5);
+
+        let v_r_cn6 = ADS_SCAN_TABLE.latest_voltage(SCAN_V_R_CN6, max_age);
+        log::info!("V_R_CN6: {:?}", v_r_cn6);
+
+        if let (Some(vcc), Some(v_r_cn5), Some(v_r_cn6)) = (vcc, v_r_cn5, v_r_cn6) {
+            let r1 = 3300f32;
+            let r2_cn5 = -1f32*((v_r_cn5*r1)/(v_r_cn5-vcc));
+            let r2_cn6 = -1f32*((v_r_cn6*r1)/(v_r_cn6-vcc));
+
+            let c_cn5 = ntc_ohm_to_celsius(r2_cn5, 50000f32, 4016f32);
+            let c_cn6 = ntc_ohm_to_celsius(r2_cn6, 50000f32, 4016f32);
+
+            log::info!("R2_CN5: {:?} Ohm, R2_CN6: {:?} Ohm, C CN5: {:?} C CN6: {:?}", r2_cn5, r2_cn6, c_cn5, c_cn6);
+        } else {
+            log::warn!("NTC channels faulted, skipping temperature calculation");
+        }
+
+
+        if fdc1004_present {
+            if let Err(e) = level.handle_commands(&mut fdc1004, &mut i2c, &LEVEL).await {
+                log::warn!("Level command failed: {:?}", e);
+            }
+        }
+
+        if fdc1004_present && level.get_calibration().is_some() {
+            match level.update(&mut fdc1004, &mut i2c, &LEVEL).await {
+                Ok((state, event)) => {
+                    log::info!("Level: {:?} % {:?} mm", state.percent, state.millimetres);
+                    if let Some(event) = event {
+                        log::warn!("Level event: {:?}", event);
+                    }
+                }
+                Err(e) => log::warn!("Level measurement failed: {:?}", e),
+            }
+        } else if fdc1004_present {
+            // Uncalibrated probe, only the raw level electrode is meaningful
+            let cap = fdc1004.read_capacitance(&mut i2c, level_probe::ELECTRODES.level).await;
+            match cap {
+                Ok(fdc1004::SuccessfulMeasurement::MeasurementInRange(cap)) => log::info!("Cap: {}", cap.capacitance()),
+                Ok(fdc1004::SuccessfulMeasurement::Overflow) => log::info!("Overflow"),
+                Ok(fdc1004::SuccessfulMeasurement::Underflow) => log::info!("Underflow"),
+                Err(e) => log::warn!("FDC1004 measurement failed: {:?}", e),
+            }
+        }
+
+        Timer::after_millis(1000).await;
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+
+    let board_io = board_revisions::apec_r0b::get_board_io(p);
+
+    let driver = Driver::new(board_io.usb, Irqs);
+    spawner.spawn(logger_task(driver)).unwrap();
+
+    let mut sr = ShiftRegister::new(C595Chain::new(
+        Output::new(board_io.serial_pin, Level::Low),
+        Output::new(board_io.storage_register_clock_pin, Level::Low),
+        Output::new(board_io.shift_register_clock_pin, Level::Low),
+    ));
+
+    if let Some(ng) = board_io.ng_pin {
+        SR_OUTPUT_ENABLE.set_pin(Output::new(ng, Level::High));
+        sr = sr.output_enable(&SR_OUTPUT_ENABLE);
+    }
+    if let Some(srclr) = board_io.srclr_pin {
+        sr = sr.clear_pin(Output::new(srclr, Level::High));
+    }
+
+    let _txs108e_oe = Output::new(board_io.txs0108e_oe_pin, Level::High);
+    let sr_out = iox::shift_register_frame::ShiftRegisterOutputs::<SHIFT_REGISTER_STAGES>::new();
+    let led = Output::new(board_io.led_pin.unwrap(), Level::Low);
+
+/*    if (board_io.led_pwm.is_some()) {
+        let led_pwm = PwmSlice::new(10_000, 20f32, 20f32, board_io.led_pwm.unwrap());
+        unwrap!(spawner.spawn(glow_led(led_pwm)));
+    }
+    if (board_io.cn9_3_pwm.is_some()) {
+        let cn9_3_pwm = PwmSlice::new(10_000, 20f32, 20f32, board_io.cn9_3_pwm.unwrap());
+        unwrap!(spawner.spawn(glow_cn6(cn9_3_pwm)));
+    }*/
+
+    let i2c = board_io.i2c0;
+
+    unwrap!(spawner.spawn(i2c_task(i2c)));
+    unwrap!(spawner.spawn(do_stuff(sr, led)));
+
+    loop {
+        Timer::after_secs(1).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn glow_cn6(mut led: PwmSlice<'static, PWM_CH6>) {
+    let mut counter = 0;
+    loop {
+        counter += 1;
+        led.set_duty_cycle_b(counter as f32 / 10f32);
+        Timer::after_millis(10).await;
+
+        if (counter > 300) {
+            counter = 0;
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn glow_led(mut led: PwmSlice<'static, PWM_CH4>) {
+    let mut counter = 0;
+    loop {
+        counter += 1;
+        led.set_duty_cycle_b((counter % 1000) as f32 / 10f32);
+        Timer::after_millis(5).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn do_stuff(
+    mut sr: ShiftRegister<'static, C595Chain<'static, SHIFT_REGISTER_STAGES>, SHIFT_REGISTER_STAGES>,
+    mut led: Output<'static, AnyPin>,
+) {
+    let mut counter = 0;
+    loop {
+        counter += 1;
+        //log::info!("counter: {}", counter);
+        
+        led.set_low();
+        sr.set_output(board_revisions::apec_r0b::shift_register_positions::JP2_FA7, true);
+        sr.flush_then_clear().await;
+
+        Timer::after_millis(5000).await;
+
+        led.set_high();
+        sr.set_output(board_revisions::apec_r0b::shift_register_positions::JP2_FA8, true);
+        sr.flush_then_clear().await;
+
+        Timer::after_millis(5000).await;
+    }
+}
+
+fn ntc_ohm_to_celsius(ohm: f32, r25: f32, b: f32) -> f32 {
+    let ln_ratio = logf(ohm / r25);
+    let t_kelvin = 1.0 / (ln_ratio / b + 1.0 / 298.15);
+    t_kelvin - 273.15
+}
@@ -1,6 +1,9 @@
+#[cfg(target_os = "none")]
 pub(crate) mod binary_output;
+#[cfg(target_os = "none")]
 pub(crate) mod analog_output;
 pub(crate) mod analog_input;
+pub(crate) mod shift_register_frame;
 
 pub(crate) trait Flushable {
     async fn flush(&mut self);
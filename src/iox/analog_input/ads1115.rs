@@ -1,25 +1,59 @@
 #![no_std]
 
+use core::fmt;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::channel::Sender;
+use embassy_time::{with_timeout, Duration, Instant, Ticker, Timer};
+use embedded_hal_1::digital::InputPin;
+use embedded_hal_async::digital::Wait;
 use embedded_hal_async::i2c::I2c;
+use fixed::types::I16F16;
+use heapless::LinearMap;
+use crate::iox::analog_input::ads1115::calibration::Calibration;
+
+pub(crate) mod calibration;
+pub(crate) mod filter;
+pub(crate) mod scan;
 
 static CONVERSION_REGISTER : u8 = 0b00;
 static CONFIG_REGISTER     : u8 = 0b01;
 static LO_THRESH_REGISTER  : u8 = 0b10;
 static HI_THRESH_REGISTER  : u8 = 0b11;
 
+static OS_MASK : u16 = 1<<15;
+
+static CONVERSION_POLL_RETRIES : u8 = 10;
+
+const MAX_CALIBRATIONS : usize = 8;
+
 #[derive(Debug)]
-pub enum ADSError{
+pub enum ADSError<E = ()>{
     WrongAddress,
     ModeSetToSingle,
+    ConversionTimeout,
+    ConfigMismatch,
+    ReadyPinError,
+    ThresholdOutOfRange,
+    UnsupportedByVariant,
+    CalibrationTableFull,
+    CalibrationFailed,
+    InvalidScanEntry,
+    I2CError(E),
 }
 
-#[derive(Debug)]
+impl<E> ADSError<E> {
+    fn from_i2c(e: E) -> Self {
+        ADSError::I2CError(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
 enum OSW{
     StartConversion,
     Idle,
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
 enum OSR{
     PerformingConversion,
     DeviceIdle,
@@ -55,7 +89,7 @@ impl Default for OSR{
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
 pub enum InputMultiplexer{
     AIN0AIN1,
     AIN0AIN3,
@@ -101,7 +135,7 @@ impl Default for InputMultiplexer{
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
 pub enum ProgramableGainAmplifier{
     V6_144,
     V4_096,
@@ -133,6 +167,61 @@ impl ProgramableGainAmplifier{
             _     => ProgramableGainAmplifier::V0_256,
         }
     }
+
+    /// Full scale range in volts
+    fn full_scale(&self) -> f32{
+        match self {
+            ProgramableGainAmplifier::V0_256 => 0.256f32,
+            ProgramableGainAmplifier::V0_512 => 0.512f32,
+            ProgramableGainAmplifier::V1_024 => 1.024f32,
+            ProgramableGainAmplifier::V2_048 => 2.048f32,
+            ProgramableGainAmplifier::V4_096 => 4.096f32,
+            ProgramableGainAmplifier::V6_144 => 6.144f32,
+        }
+    }
+
+    /// Ranges ordered from the coarsest to the finest
+    const RANGES: [ProgramableGainAmplifier; 6] = [
+        ProgramableGainAmplifier::V6_144,
+        ProgramableGainAmplifier::V4_096,
+        ProgramableGainAmplifier::V2_048,
+        ProgramableGainAmplifier::V1_024,
+        ProgramableGainAmplifier::V0_512,
+        ProgramableGainAmplifier::V0_256,
+    ];
+
+    fn range_index(&self) -> usize{
+        (self.bits() >> 9) as usize
+    }
+
+    /// Tightest range that holds volts with (1 - margin) of its full scale to spare
+    fn tightest_for(volts: f32, margin: f32) -> Self{
+        Self::RANGES.iter().rev()
+            .find(|r| volts.abs() < r.full_scale() * (1f32 - margin))
+            .copied()
+            .unwrap_or(ProgramableGainAmplifier::V6_144)
+    }
+
+    /// Full scale range in millivolts
+    fn full_scale_mv(&self) -> i32{
+        match self {
+            ProgramableGainAmplifier::V0_256 => 256,
+            ProgramableGainAmplifier::V0_512 => 512,
+            ProgramableGainAmplifier::V1_024 => 1024,
+            ProgramableGainAmplifier::V2_048 => 2048,
+            ProgramableGainAmplifier::V4_096 => 4096,
+            ProgramableGainAmplifier::V6_144 => 6144,
+        }
+    }
+
+    /// Converts volts into a conversion code, None when outside of the full scale range
+    fn volts_to_code(&self, volts: f32) -> Option<i16>{
+        let fs = self.full_scale();
+        if volts < -fs || volts > fs {
+            return None;
+        }
+        Some((volts * 32768f32 / fs) as i16)
+    }
 }
 
 impl Default for ProgramableGainAmplifier{
@@ -141,7 +230,7 @@ impl Default for ProgramableGainAmplifier{
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
 pub enum Mode{
     Continuous,
     Signle,
@@ -169,7 +258,7 @@ impl Default for Mode {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
 pub enum DataRate{
     SPS8,
     SPS16,
@@ -207,6 +296,39 @@ impl DataRate{
             _     => DataRate::SPS860,
         }
     }
+
+    /// Samples per second, the 12 bit devices map the same codes to 128 - 3300 SPS
+    pub fn samples_per_second(&self, variant: Variant) -> u16{
+        match (variant.is_12_bit(), self) {
+            (false, DataRate::SPS8)   => 8,
+            (false, DataRate::SPS16)  => 16,
+            (false, DataRate::SPS32)  => 32,
+            (false, DataRate::SPS64)  => 64,
+            (false, DataRate::SPS128) => 128,
+            (false, DataRate::SPS250) => 250,
+            (false, DataRate::SPS475) => 475,
+            (false, DataRate::SPS860) => 860,
+            (true, DataRate::SPS8)    => 128,
+            (true, DataRate::SPS16)   => 250,
+            (true, DataRate::SPS32)   => 490,
+            (true, DataRate::SPS64)   => 920,
+            (true, DataRate::SPS128)  => 1600,
+            (true, DataRate::SPS250)  => 2400,
+            (true, DataRate::SPS475)  => 3300,
+            (true, DataRate::SPS860)  => 3300,
+        }
+    }
+
+    /// Nominal time between two conversions
+    fn period_us(&self, variant: Variant) -> u64{
+        1_000_000u64.div_ceil(self.samples_per_second(variant) as u64)
+    }
+
+    /// Worst case conversion time including the 10% oscillator tolerance
+    fn conversion_time_us(&self, variant: Variant) -> u64{
+        let period = self.period_us(variant);
+        period + period / 10
+    }
 }
 
 impl Default for DataRate{
@@ -215,7 +337,7 @@ impl Default for DataRate{
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
 pub enum ComparatorMode{
     Traditional,
     Window,
@@ -243,7 +365,7 @@ impl Default for ComparatorMode{
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
 pub enum ComparatorPolarity{
     ActiveLow,
     ActiveHigh,
@@ -271,7 +393,7 @@ impl Default for ComparatorPolarity{
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
 pub enum LatchingComparator{
     NonLatching,
     Latching,
@@ -299,7 +421,7 @@ impl Default for LatchingComparator{
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
 pub enum ComparatorQueue{
     AsserAfterOne,
     AsserAfterTwo,
@@ -333,7 +455,7 @@ impl Default for ComparatorQueue{
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, defmt::Format)]
 pub struct ADS111xConfig{
     osw: OSW,
     osr: OSR,
@@ -413,16 +535,188 @@ impl ADS111xConfig{
         self.comp_que = cq;
         self
     }
+
+    pub fn get_mux(&self) -> InputMultiplexer{
+        self.mux
+    }
+
+    pub fn get_pga(&self) -> ProgramableGainAmplifier{
+        self.pga
+    }
+
+    pub fn get_mode(&self) -> Mode{
+        self.mode
+    }
+
+    pub fn get_dr(&self) -> DataRate{
+        self.dr
+    }
+
+    pub fn get_comp_mode(&self) -> ComparatorMode{
+        self.comp_mode
+    }
+
+    pub fn get_comp_pol(&self) -> ComparatorPolarity{
+        self.comp_pol
+    }
+
+    pub fn get_comp_lat(&self) -> LatchingComparator{
+        self.comp_lat
+    }
+
+    pub fn get_comp_que(&self) -> ComparatorQueue{
+        self.comp_que
+    }
+
+    /// Only meaningful for a config read back from the device
+    pub fn is_converting(&self) -> bool{
+        self.osr == OSR::PerformingConversion
+    }
+}
+
+/// Supported members of the ADS101x/ADS111x family
+/// ADS1x13 has neither mux, PGA nor comparator, ADS1x14 adds PGA and comparator
+/// and only ADS1x15 has the four input mux
+#[derive(Debug, Clone, Copy, PartialEq, Default, defmt::Format)]
+pub enum Variant{
+    ADS1013,
+    ADS1014,
+    ADS1015,
+    ADS1113,
+    ADS1114,
+    #[default]
+    ADS1115,
+}
+
+impl Variant{
+    pub fn is_12_bit(&self) -> bool{
+        matches!(self, Variant::ADS1013 | Variant::ADS1014 | Variant::ADS1015)
+    }
+
+    pub fn has_mux(&self) -> bool{
+        matches!(self, Variant::ADS1015 | Variant::ADS1115)
+    }
+
+    pub fn has_pga(&self) -> bool{
+        !matches!(self, Variant::ADS1013 | Variant::ADS1113)
+    }
+
+    pub fn has_comparator(&self) -> bool{
+        self.has_pga()
+    }
+
+    /// Conversion code that equals the full scale voltage
+    fn full_scale_code(&self) -> u16{
+        match self.is_12_bit() {
+            true  => 2048,
+            false => 32768,
+        }
+    }
+
+    /// Checks that config only uses features this variant has
+    fn check_config<E>(&self, config: &ADS111xConfig) -> Result<(), ADSError<E>>{
+        if !self.has_mux() && config.mux != InputMultiplexer::AIN0AIN1 {
+            return Err(ADSError::UnsupportedByVariant);
+        }
+        if !self.has_pga() && config.pga != ProgramableGainAmplifier::V2_048 {
+            return Err(ADSError::UnsupportedByVariant);
+        }
+        if !self.has_comparator() && !matches!(config.comp_que, ComparatorQueue::Disable) {
+            return Err(ADSError::UnsupportedByVariant);
+        }
+        Ok(())
+    }
+}
+
+/// Conversion code as read from the device together with the PGA it was taken with
+/// No calibration is applied and no floats are involved in any of the conversions
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct RawReading{
+    pub code: i16,
+    pub pga: ProgramableGainAmplifier,
+    variant: Variant,
+}
+
+impl RawReading{
+    pub fn millivolts(&self) -> I16F16{
+        let lsb_bits = 65536 / i32::from(self.variant.full_scale_code());
+        I16F16::from_bits(i32::from(self.code) * self.pga.full_scale_mv() * lsb_bits)
+    }
+
+    /// Conversion hit the end of the range, the input may be beyond it
+    pub fn is_saturated(&self) -> bool{
+        let full_scale = i32::from(self.variant.full_scale_code());
+        i32::from(self.code) >= full_scale - 1 || i32::from(self.code) <= -full_scale
+    }
+
+    pub fn microvolts(&self) -> i32{
+        let uv = i64::from(self.code) * i64::from(self.pga.full_scale_mv()) * 1000 / i64::from(self.variant.full_scale_code());
+        uv as i32
+    }
+}
+
+/// Snapshot of all four device registers
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct RegisterDump{
+    pub variant: Variant,
+    pub config: ADS111xConfig,
+    pub conversion: RawReading,
+    pub lo_thresh: i16,
+    pub hi_thresh: i16,
+}
+
+impl fmt::Display for RegisterDump{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let c = &self.config;
+        writeln!(f, "variant:    {:?}", self.variant)?;
+        writeln!(f, "config:     0x{:04x} converting={:?}", c.bits(), c.is_converting())?;
+        writeln!(f, "  mux={:?} pga={:?} mode={:?} dr={:?}", c.mux, c.pga, c.mode, c.dr)?;
+        writeln!(f, "  comp_mode={:?} comp_pol={:?} comp_lat={:?} comp_que={:?}", c.comp_mode, c.comp_pol, c.comp_lat, c.comp_que)?;
+        writeln!(f, "conversion: {} ({} uV)", self.conversion.code, self.conversion.microvolts())?;
+        writeln!(f, "lo_thresh:  0x{:04x}", self.lo_thresh)?;
+        write!(f, "hi_thresh:  0x{:04x}", self.hi_thresh)
+    }
+}
+
+/// Outcome of stream_continuous_on_ready
+#[derive(Debug, Default, Clone, Copy, PartialEq, defmt::Format)]
+pub struct StreamReport{
+    /// Samples that did not fit into the channel
+    pub dropped: usize,
+    /// Conversions that finished before the previous one was read
+    pub missed: usize,
+}
+
+/// What the ALERT/RDY pin is set up for, both uses are configured through the threshold registers
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum AlertPin{
+    #[default]
+    Unused,
+    ConversionReady,
+    WindowAlarm,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sample{
+    pub voltage: f32,
+    pub timestamp: Instant,
 }
 
 pub(crate) struct ADS111x {
     address: u8,
     config: ADS111xConfig,
+    variant: Variant,
+    calibrations: LinearMap<(InputMultiplexer, ProgramableGainAmplifier), Calibration, MAX_CALIBRATIONS>,
+    alert_pin: AlertPin,
 }
 
 impl ADS111x
 {
     pub fn new(address: u8, config: ADS111xConfig) -> Result<Self, ADSError>{
+        Self::new_variant(address, config, Variant::default())
+    }
+
+    pub fn new_variant(address: u8, config: ADS111xConfig, variant: Variant) -> Result<Self, ADSError>{
         match address {
             0b1001000 => {},
             0b1001001 => {},
@@ -430,49 +724,345 @@ impl ADS111x
             0b1001011 => {},
             _ => return Err(ADSError::WrongAddress),
         }
-        Ok(ADS111x{ address, config} )
+        variant.check_config(&config)?;
+        Ok(ADS111x{ address, config, variant, calibrations: LinearMap::new(), alert_pin: AlertPin::Unused } )
     }
 
     ///Writes self configuration to device
     ///Config can be used to update configuration
-    pub async fn write_config<I2C, E> (&mut self, i2c: &mut I2C, config: Option<ADS111xConfig>) -> Result<(), E> where I2C: I2c<Error = E> {
+    pub async fn write_config<I2C, E> (&mut self, i2c: &mut I2C, config: Option<ADS111xConfig>) -> Result<(), ADSError<E>> where I2C: I2c<Error = E> {
         if let Some(conf) = config{
+            self.variant.check_config(&conf)?;
             self.config = conf;
         }
+        self.variant.check_config(&self.config)?;
         self.config.osw = OSW::Idle;
         let conf = self.config.bits().to_be_bytes();
-        i2c.write(self.address, &[CONFIG_REGISTER, conf[0], conf[1]]).await
+        i2c.write(self.address, &[CONFIG_REGISTER, conf[0], conf[1]]).await.map_err(ADSError::from_i2c)
     }
 
-    pub async fn read_config<I2C, E>(&mut self, i2c: &mut I2C) -> Result<ADS111xConfig, E> where I2C: I2c<Error = E> {
+    pub async fn read_config<I2C, E>(&mut self, i2c: &mut I2C) -> Result<ADS111xConfig, ADSError<E>> where I2C: I2c<Error = E> {
         self.config.osw = OSW::Idle;
         let mut conf = [0, 0];
 
-        i2c.write_read(self.address, &[CONFIG_REGISTER], &mut conf).await.and(Ok(ADS111xConfig::from_bits(u16::from_be_bytes(conf))))
+        i2c.write_read(self.address, &[CONFIG_REGISTER], &mut conf).await.map_err(ADSError::from_i2c)?;
+        Ok(ADS111xConfig::from_bits(u16::from_be_bytes(conf)))
+    }
+
+    /// Writes the configuration, reads it back and compares all fields
+    /// Config can be used to update configuration
+    pub async fn verify_config<I2C, E>(&mut self, i2c: &mut I2C, config: Option<ADS111xConfig>) -> Result<(), ADSError<E>> where I2C: I2c<Error = E> {
+        self.write_config(i2c, config).await?;
+        let readback = self.read_config(i2c).await?;
+
+        if readback.bits() != self.config.bits() {
+            return Err(ADSError::ConfigMismatch);
+        }
+        Ok(())
+    }
+
+    /// Reads config, conversion and both threshold registers
+    pub async fn dump_registers<I2C, E>(&mut self, i2c: &mut I2C) -> Result<RegisterDump, ADSError<E>> where I2C: I2c<Error = E> {
+        let config = self.read_config(i2c).await?;
+
+        let mut conversion = [0, 0];
+        i2c.write_read(self.address, &[CONVERSION_REGISTER], &mut conversion).await.map_err(ADSError::from_i2c)?;
+        let mut lo_thresh = [0, 0];
+        i2c.write_read(self.address, &[LO_THRESH_REGISTER], &mut lo_thresh).await.map_err(ADSError::from_i2c)?;
+        let mut hi_thresh = [0, 0];
+        i2c.write_read(self.address, &[HI_THRESH_REGISTER], &mut hi_thresh).await.map_err(ADSError::from_i2c)?;
+
+        Ok(RegisterDump{
+            variant: self.variant,
+            config,
+            conversion: RawReading{ code: self.code_from_register(conversion), pga: config.pga, variant: self.variant },
+            lo_thresh: i16::from_be_bytes(lo_thresh),
+            hi_thresh: i16::from_be_bytes(hi_thresh),
+        })
+    }
+
+    /// Stores the calibration applied to every reading taken with mux and pga
+    pub fn set_calibration<E>(&mut self, mux: InputMultiplexer, pga: ProgramableGainAmplifier, calibration: Calibration) -> Result<(), ADSError<E>>{
+        self.calibrations.insert((mux, pga), calibration).map_err(|_| ADSError::CalibrationTableFull)?;
+        Ok(())
+    }
+
+    pub fn calibration(&self, mux: InputMultiplexer, pga: ProgramableGainAmplifier) -> Option<Calibration>{
+        self.calibrations.get(&(mux, pga)).copied()
+    }
+
+    pub fn clear_calibration(&mut self, mux: InputMultiplexer, pga: ProgramableGainAmplifier){
+        self.calibrations.remove(&(mux, pga));
     }
 
     /// Perform single read when mode set to single
     /// ADC is in low power state until requested and will go back after conversion
-    /// Will block until converstion is ready
+    /// Sleeps for the conversion time of the configured data rate, then polls
+    /// a bounded number of times before giving up with ConversionTimeout
     /// Mux can be used to reconfigure what ADC input to read
-    pub async fn read_single_voltage<I2C, E>(&mut self, i2c: &mut I2C, mux: Option<InputMultiplexer>) -> Result<f32, E> where I2C: I2c<Error = E> {
+    pub async fn read_single_voltage<I2C, E>(&mut self, i2c: &mut I2C, mux: Option<InputMultiplexer>) -> Result<f32, ADSError<E>> where I2C: I2c<Error = E> {
+        self.convert_single(i2c, mux).await?;
+        self.read_voltage(i2c).await
+    }
+
+    /// Same as read_single_voltage but without applying the stored calibration
+    pub async fn read_single_uncalibrated_voltage<I2C, E>(&mut self, i2c: &mut I2C, mux: Option<InputMultiplexer>) -> Result<f32, ADSError<E>> where I2C: I2c<Error = E> {
+        self.convert_single(i2c, mux).await?;
+        let code = self.read_code(i2c).await?;
+        Ok(self.code_to_volts(code))
+    }
+
+    /// Same as read_single_voltage but returns the raw conversion code
+    pub async fn read_single_raw<I2C, E>(&mut self, i2c: &mut I2C, mux: Option<InputMultiplexer>) -> Result<RawReading, ADSError<E>> where I2C: I2c<Error = E> {
+        self.convert_single(i2c, mux).await?;
+        self.read_raw(i2c).await
+    }
+
+    /// Same as read_single_voltage but in fixed point millivolts
+    pub async fn read_single_millivolts<I2C, E>(&mut self, i2c: &mut I2C, mux: Option<InputMultiplexer>) -> Result<I16F16, ADSError<E>> where I2C: I2c<Error = E> {
+        Ok(self.read_single_raw(i2c, mux).await?.millivolts())
+    }
+
+    /// Same as read_single_voltage but in integer microvolts
+    pub async fn read_single_microvolts<I2C, E>(&mut self, i2c: &mut I2C, mux: Option<InputMultiplexer>) -> Result<i32, ADSError<E>> where I2C: I2c<Error = E> {
+        Ok(self.read_single_raw(i2c, mux).await?.microvolts())
+    }
+
+    /// Single read that picks the PGA by itself
+    /// Measures at previous (or V6_144 when None), re-measures at V6_144 when that conversion saturates
+    /// and once at the tightest PGA that holds the reading when that is finer, so at most three
+    /// conversions are taken unless the finer one saturates again
+    /// hysteresis is the fraction of a range's full scale a reading has to stay below before
+    /// a finer range is chosen, pass the returned PGA back as previous to avoid range flapping
+    /// The device's mux and pga are restored afterwards, also when a conversion fails
+    pub async fn read_single_voltage_autorange<I2C, E>(&mut self, i2c: &mut I2C, mux: Option<InputMultiplexer>, previous: Option<ProgramableGainAmplifier>, hysteresis: f32) -> Result<(f32, ProgramableGainAmplifier), ADSError<E>> where I2C: I2c<Error = E> {
+        if !self.variant.has_pga() {
+            return Err(ADSError::UnsupportedByVariant);
+        }
+
+        let (saved_mux, saved_pga) = (self.config.mux, self.config.pga);
         if let Some(m) = mux{
             self.config.mux = m;
         }
+
+        let result = self.autorange(i2c, previous.unwrap_or(ProgramableGainAmplifier::V6_144), hysteresis).await;
+        self.config.mux = saved_mux;
+        self.config.pga = saved_pga;
+
+        result
+    }
+
+    async fn autorange<I2C, E>(&mut self, i2c: &mut I2C, mut pga: ProgramableGainAmplifier, hysteresis: f32) -> Result<(f32, ProgramableGainAmplifier), ADSError<E>> where I2C: I2c<Error = E> {
+        let mut stepped_up = false;
+        loop {
+            self.config.pga = pga;
+            let raw = self.read_single_raw(i2c, None).await?;
+
+            if raw.is_saturated() && pga != ProgramableGainAmplifier::V6_144 {
+                pga = ProgramableGainAmplifier::V6_144;
+                continue;
+            }
+
+            let voltage = self.code_to_volts(raw.code);
+            let target = ProgramableGainAmplifier::tightest_for(voltage, hysteresis);
+            if !stepped_up && target.range_index() > pga.range_index() {
+                pga = target;
+                stepped_up = true;
+                continue;
+            }
+
+            return Ok((self.apply_calibration(voltage), pga));
+        }
+    }
+
+    /// Starts a single shot conversion and waits until it is finished
+    async fn convert_single<I2C, E>(&mut self, i2c: &mut I2C, mux: Option<InputMultiplexer>) -> Result<(), ADSError<E>> where I2C: I2c<Error = E> {
+        if let Some(m) = mux{
+            self.config.mux = m;
+        }
+        self.variant.check_config(&self.config)?;
         self.config.osw = OSW::StartConversion;
         let config = self.config.bits().to_be_bytes();
         let mut conf = [0, 0];
 
-        i2c.write_read(self.address, &[CONFIG_REGISTER, config[0], config[1]], &mut conf).await?;
+        i2c.write_read(self.address, &[CONFIG_REGISTER, config[0], config[1]], &mut conf).await.map_err(ADSError::from_i2c)?;
+
+        if u16::from_be_bytes(conf) & !OS_MASK != self.config.bits() & !OS_MASK {
+            return Err(ADSError::ConfigMismatch);
+        }
+
+        let conversion_time = self.config.dr.conversion_time_us(self.variant);
+        Timer::after_micros(conversion_time).await;
+        i2c.write_read(self.address, &[CONFIG_REGISTER], &mut conf).await.map_err(ADSError::from_i2c)?;
 
+        let mut retries = 0;
         while OSR::from_bits(u16::from_be_bytes(conf)) == OSR::PerformingConversion{
-            i2c.write_read(self.address, &[CONFIG_REGISTER], &mut conf).await?;
+            if retries == CONVERSION_POLL_RETRIES {
+                return Err(ADSError::ConversionTimeout);
+            }
+            retries += 1;
+
+            Timer::after_micros(conversion_time / 10).await;
+            i2c.write_read(self.address, &[CONFIG_REGISTER], &mut conf).await.map_err(ADSError::from_i2c)?;
         }
 
+        Ok(())
+    }
+
+    /// Puts the ALERT/RDY pin into conversion ready mode
+    /// Hi_thresh MSB is set and Lo_thresh MSB is cleared, the comparator queue
+    /// is enabled if it was disabled as the pin would otherwise stay inactive
+    /// Fails with ConfigMismatch while a window alarm is set, call disable_alert_pin first
+    pub async fn enable_conversion_ready_pin<I2C, E>(&mut self, i2c: &mut I2C) -> Result<(), ADSError<E>> where I2C: I2c<Error = E> {
+        if !self.variant.has_comparator() {
+            return Err(ADSError::UnsupportedByVariant);
+        }
+        if self.alert_pin == AlertPin::WindowAlarm {
+            return Err(ADSError::ConfigMismatch);
+        }
+        self.set_high_treshold(i2c, i16::MIN).await?;
+        self.set_low_treshold(i2c, 0).await?;
+
+        if matches!(self.config.comp_que, ComparatorQueue::Disable) {
+            self.config.comp_que = ComparatorQueue::AsserAfterOne;
+        }
+        self.write_config(i2c, None).await?;
+        self.alert_pin = AlertPin::ConversionReady;
+        Ok(())
+    }
+
+    /// Disables the comparator, the ALERT/RDY pin goes inactive and is free for either use again
+    pub async fn disable_alert_pin<I2C, E>(&mut self, i2c: &mut I2C) -> Result<(), ADSError<E>> where I2C: I2c<Error = E> {
+        self.config.comp_que = ComparatorQueue::Disable;
+        self.write_config(i2c, None).await?;
+        self.alert_pin = AlertPin::Unused;
+        Ok(())
+    }
+
+    /// Perform single read like read_single_voltage but await the ALERT/RDY pin
+    /// instead of polling the config register
+    /// enable_conversion_ready_pin has to be called first
+    pub async fn read_single_voltage_on_ready<I2C, E, P>(&mut self, i2c: &mut I2C, ready: &mut P, mux: Option<InputMultiplexer>) -> Result<f32, ADSError<E>> where I2C: I2c<Error = E>, P: Wait {
+        if let Some(m) = mux{
+            self.config.mux = m;
+        }
+        self.variant.check_config(&self.config)?;
+        self.config.osw = OSW::StartConversion;
+        let config = self.config.bits().to_be_bytes();
+        self.config.osw = OSW::Idle;
+
+        i2c.write(self.address, &[CONFIG_REGISTER, config[0], config[1]]).await.map_err(ADSError::from_i2c)?;
+
+        self.wait_conversion_ready(ready).await?;
         self.read_voltage(i2c).await
     }
 
-    pub async fn check_cnversion_ready<I2C, E>(&mut self, i2c: &mut I2C) -> Result<bool, E> where I2C: I2c<Error = E> {
+    /// Waits for the ALERT/RDY pin to signal a finished conversion
+    /// In single shot mode the pin stays asserted until the next conversion is started,
+    /// in continuous mode it only pulses so an edge is awaited instead
+    /// Fails with ConfigMismatch while the pin is set up as window alarm instead
+    pub async fn wait_conversion_ready<E, P>(&self, ready: &mut P) -> Result<(), ADSError<E>> where P: Wait {
+        if self.alert_pin == AlertPin::WindowAlarm {
+            return Err(ADSError::ConfigMismatch);
+        }
+        let active_high = matches!(self.config.comp_pol, ComparatorPolarity::ActiveHigh);
+        let continuous = self.config.mode == Mode::Continuous;
+        let timeout = Duration::from_micros(self.config.dr.conversion_time_us(self.variant) * 2);
+
+        let wait = async {
+            match (continuous, active_high) {
+                (true, false)  => ready.wait_for_falling_edge().await,
+                (true, true)   => ready.wait_for_rising_edge().await,
+                (false, false) => ready.wait_for_low().await,
+                (false, true)  => ready.wait_for_high().await,
+            }
+        };
+
+        match with_timeout(timeout, wait).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(ADSError::ReadyPinError),
+            Err(_)     => Err(ADSError::ConversionTimeout),
+        }
+    }
+
+    /// Switches the device into continuous conversion mode
+    /// The conversion register is then updated at the given data rate without further requests
+    pub async fn start_continuous<I2C, E>(&mut self, i2c: &mut I2C, mux: Option<InputMultiplexer>, dr: DataRate) -> Result<(), ADSError<E>> where I2C: I2c<Error = E> {
+        if let Some(m) = mux{
+            self.config.mux = m;
+        }
+        self.config.dr = dr;
+        self.config.mode = Mode::Continuous;
+        self.write_config(i2c, None).await
+    }
+
+    /// Puts the device back into single shot mode, it powers down after the running conversion
+    pub async fn stop_continuous<I2C, E>(&mut self, i2c: &mut I2C) -> Result<(), ADSError<E>> where I2C: I2c<Error = E> {
+        self.config.mode = Mode::Signle;
+        self.write_config(i2c, None).await
+    }
+
+    /// Streams one timestamped sample per conversion into sender, paced by the ALERT/RDY pin
+    /// enable_conversion_ready_pin and start_continuous have to be called first
+    /// Every sample is timestamped at the ready edge, so it is the time of the conversion and not of the read
+    /// Samples that do not fit into the channel are dropped, conversions that finished while the
+    /// previous one was still being read are counted as missed
+    pub async fn stream_continuous_on_ready<I2C, E, P, M, const N: usize>(&mut self, i2c: &mut I2C, ready: &mut P, sender: Sender<'_, M, Sample, N>, samples: usize) -> Result<StreamReport, ADSError<E>> where I2C: I2c<Error = E>, P: Wait, M: RawMutex {
+        if self.config.mode != Mode::Continuous {
+            return Err(ADSError::ModeSetToSingle);
+        }
+
+        let period = self.config.dr.period_us(self.variant);
+        let mut report = StreamReport::default();
+        let mut last: Option<Instant> = None;
+
+        for _ in 0..samples {
+            self.wait_conversion_ready(ready).await?;
+            let timestamp = Instant::now();
+            if let Some(last) = last {
+                // rounding to whole periods absorbs the +-10% tolerance of the internal oscillator
+                let periods = ((timestamp - last).as_micros() + period / 2) / period;
+                report.missed += periods.saturating_sub(1) as usize;
+            }
+            last = Some(timestamp);
+
+            let voltage = self.read_voltage(i2c).await?;
+            if sender.try_send(Sample{ voltage, timestamp }).is_err() {
+                report.dropped += 1;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Fallback for boards without the ALERT/RDY pin wired, prefer stream_continuous_on_ready
+    /// Reads the conversion register on a timer at the nominal data rate, which is not synchronised
+    /// with the device: its oscillator is only accurate to +-10%, so a conversion can be returned twice
+    /// or skipped without notice, and timestamps are the time of the read
+    /// start_continuous has to be called first
+    /// Samples that do not fit into the channel are dropped, the number of dropped samples is returned
+    pub async fn stream_continuous<I2C, E, M, const N: usize>(&mut self, i2c: &mut I2C, sender: Sender<'_, M, Sample, N>, samples: usize) -> Result<usize, ADSError<E>> where I2C: I2c<Error = E>, M: RawMutex {
+        if self.config.mode != Mode::Continuous {
+            return Err(ADSError::ModeSetToSingle);
+        }
+
+        let mut ticker = Ticker::every(Duration::from_micros(self.config.dr.period_us(self.variant)));
+        let mut dropped = 0;
+
+        for _ in 0..samples {
+            ticker.next().await;
+            let timestamp = Instant::now();
+            let voltage = self.read_voltage(i2c).await?;
+
+            if sender.try_send(Sample{ voltage, timestamp }).is_err() {
+                dropped += 1;
+            }
+        }
+
+        Ok(dropped)
+    }
+
+    pub async fn check_cnversion_ready<I2C, E>(&mut self, i2c: &mut I2C) -> Result<bool, ADSError<E>> where I2C: I2c<Error = E> {
         Ok(self.read_config(i2c).await?.osr == OSR::DeviceIdle)
     }
 
@@ -481,29 +1071,566 @@ impl ADS111x
     /// will return 0 when conversion was still ongoing
     /// You can use check_coversion_ready if needed
     /// only works when Mode is Continuous
-    pub async fn read_voltage<I2C, E>(&mut self, i2c: &mut I2C) -> Result<f32, E> where I2C: I2c<Error = E> {
-        let mut voltage = [0, 0];
-        i2c.write_read(self.address, &[CONVERSION_REGISTER], &mut voltage).await?;
-        let val = i16::from_be_bytes(voltage);
-        let pga = match self.config.pga{
-            ProgramableGainAmplifier::V0_256 => 0.256f32,
-            ProgramableGainAmplifier::V0_512 => 0.512f32,
-            ProgramableGainAmplifier::V1_024 => 1.024f32,
-            ProgramableGainAmplifier::V2_048 => 2.048f32,
-            ProgramableGainAmplifier::V4_096 => 4.096f32,
-            ProgramableGainAmplifier::V6_144 => 6.144f32,
-        };
+    /// The calibration stored for the current mux and pga is applied
+    pub async fn read_voltage<I2C, E>(&mut self, i2c: &mut I2C) -> Result<f32, ADSError<E>> where I2C: I2c<Error = E> {
+        let code = self.read_code(i2c).await?;
+        let voltage = self.code_to_volts(code);
 
-        Ok(f32::from(val) * pga / 32768f32)
+        Ok(self.apply_calibration(voltage))
     }
 
-    pub async fn set_low_treshold<I2C, E>(&mut self, i2c: &mut I2C, low_tresh: i16) -> Result<(), E> where I2C: I2c<Error = E> {
+    fn apply_calibration(&self, voltage: f32) -> f32 {
+        match self.calibration(self.config.mux, self.config.pga) {
+            Some(calibration) => calibration.apply(voltage),
+            None => voltage,
+        }
+    }
+
+    /// Reads the conversion register like read_voltage, without conversion to volts
+    pub async fn read_raw<I2C, E>(&mut self, i2c: &mut I2C) -> Result<RawReading, ADSError<E>> where I2C: I2c<Error = E> {
+        let code = self.read_code(i2c).await?;
+        Ok(RawReading{ code, pga: self.config.pga, variant: self.variant })
+    }
+
+    async fn read_code<I2C, E>(&mut self, i2c: &mut I2C) -> Result<i16, ADSError<E>> where I2C: I2c<Error = E> {
+        let mut code = [0, 0];
+        i2c.write_read(self.address, &[CONVERSION_REGISTER], &mut code).await.map_err(ADSError::from_i2c)?;
+        Ok(self.code_from_register(code))
+    }
+
+    fn code_to_volts(&self, code: i16) -> f32 {
+        f32::from(code) * self.config.pga.full_scale() / f32::from(self.variant.full_scale_code())
+    }
+
+    /// The 12 bit devices left justify their result, shift it down to the native code
+    fn code_from_register(&self, reg: [u8; 2]) -> i16 {
+        let val = i16::from_be_bytes(reg);
+        match self.variant.is_12_bit() {
+            true  => val >> 4,
+            false => val,
+        }
+    }
+
+    pub async fn set_low_treshold<I2C, E>(&mut self, i2c: &mut I2C, low_tresh: i16) -> Result<(), ADSError<E>> where I2C: I2c<Error = E> {
         let lt = low_tresh.to_be_bytes();
-        i2c.write(self.address, &[LO_THRESH_REGISTER, lt[0], lt[1]]).await
+        i2c.write(self.address, &[LO_THRESH_REGISTER, lt[0], lt[1]]).await.map_err(ADSError::from_i2c)
     }
 
-    pub async fn set_high_treshold<I2C, E>(&mut self, i2c: &mut I2C, high_tresh: i16) -> Result<(), E> where I2C: I2c<Error = E> {
+    pub async fn set_high_treshold<I2C, E>(&mut self, i2c: &mut I2C, high_tresh: i16) -> Result<(), ADSError<E>> where I2C: I2c<Error = E> {
         let ht = high_tresh.to_be_bytes();
-        i2c.write(self.address, &[HI_THRESH_REGISTER, ht[0], ht[1]]).await
+        i2c.write(self.address, &[HI_THRESH_REGISTER, ht[0], ht[1]]).await.map_err(ADSError::from_i2c)
+    }
+
+    /// Configures the comparator as latching window comparator with thresholds in volts
+    /// Thresholds are converted using the currently configured PGA and have to fit its range
+    /// ALERT/RDY asserts once queue conversions in a row are outside of [low, high]
+    /// and stays asserted until cleared with clear_alert
+    /// The comparator only sees the conversions the device makes, so a tripwire that watches the
+    /// input on its own needs continuous mode (start_continuous)
+    /// Fails with ConfigMismatch while the pin is in conversion ready mode, the alarm would
+    /// overwrite its thresholds, call disable_alert_pin first
+    pub async fn set_window_alarm<I2C, E>(&mut self, i2c: &mut I2C, low: f32, high: f32, queue: ComparatorQueue) -> Result<(), ADSError<E>> where I2C: I2c<Error = E> {
+        if !self.variant.has_comparator() {
+            return Err(ADSError::UnsupportedByVariant);
+        }
+        if self.alert_pin == AlertPin::ConversionReady {
+            return Err(ADSError::ConfigMismatch);
+        }
+        if matches!(queue, ComparatorQueue::Disable) || low >= high {
+            return Err(ADSError::ThresholdOutOfRange);
+        }
+        let low_code = self.config.pga.volts_to_code(low).ok_or(ADSError::ThresholdOutOfRange)?;
+        let high_code = self.config.pga.volts_to_code(high).ok_or(ADSError::ThresholdOutOfRange)?;
+
+        self.set_low_treshold(i2c, low_code).await?;
+        self.set_high_treshold(i2c, high_code).await?;
+
+        self.config.comp_mode = ComparatorMode::Window;
+        self.config.comp_lat = LatchingComparator::Latching;
+        self.config.comp_que = queue;
+        self.write_config(i2c, None).await?;
+        self.alert_pin = AlertPin::WindowAlarm;
+        Ok(())
+    }
+
+    /// Checks the ALERT/RDY pin for an asserted alert, honoring the configured polarity
+    pub fn alert_asserted<E, P>(&self, alert: &mut P) -> Result<bool, ADSError<E>> where P: InputPin {
+        let high = alert.is_high().map_err(|_| ADSError::ReadyPinError)?;
+        Ok(high == matches!(self.config.comp_pol, ComparatorPolarity::ActiveHigh))
+    }
+
+    /// Clears a latched alert by reading the conversion register
+    /// Returns the conversion that was read in the process
+    /// The alert asserts again on the next conversion that is still outside of the window
+    pub async fn clear_alert<I2C, E>(&mut self, i2c: &mut I2C) -> Result<f32, ADSError<E>> where I2C: I2c<Error = E> {
+        self.read_voltage(i2c).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_hal_1::i2c::ErrorKind;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    pub(super) const ADDRESS: u8 = 0x48;
+
+    #[test]
+    fn default_config_bits() {
+        assert_eq!(ADS111xConfig::default().bits(), 0x0583);
+    }
+
+    #[test]
+    fn config_field_bits() {
+        let config = ADS111xConfig::default()
+            .mux(InputMultiplexer::AIN2GND)
+            .pga(ProgramableGainAmplifier::V0_256)
+            .mode(Mode::Continuous)
+            .dr(DataRate::SPS860)
+            .comp_mode(ComparatorMode::Window)
+            .comp_pol(ComparatorPolarity::ActiveHigh)
+            .comp_lat(LatchingComparator::Latching)
+            .comp_que(ComparatorQueue::AsserAfterTwo);
+
+        assert_eq!(config.bits(), 0x6AFD);
+        assert_eq!(ADS111xConfig::from_bits(config.bits()).bits(), config.bits());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn os_bits() {
+        assert_eq!(OSW::StartConversion.bits(), 0x8000);
+        assert_eq!(OSW::Idle.bits(), 0);
+        assert_eq!(OSR::from_bits(0x8000), OSR::DeviceIdle);
+        assert_eq!(OSR::from_bits(0x7FFF), OSR::PerformingConversion);
+    }
+
+    #[test]
+    fn mux_round_trip() {
+        for mux in [
+            InputMultiplexer::AIN0AIN1, InputMultiplexer::AIN0AIN3, InputMultiplexer::AIN1AIN3, InputMultiplexer::AIN2AIN3,
+            InputMultiplexer::AIN0GND, InputMultiplexer::AIN1GND, InputMultiplexer::AIN2GND, InputMultiplexer::AIN3GND,
+        ] {
+            assert_eq!(mux.bits() & !0x7000, 0);
+            assert_eq!(InputMultiplexer::from_bits(mux.bits()), mux);
+        }
+    }
+
+    #[test]
+    fn pga_round_trip() {
+        for pga in ProgramableGainAmplifier::RANGES {
+            assert_eq!(pga.bits() & !0x0E00, 0);
+            assert_eq!(ProgramableGainAmplifier::from_bits(pga.bits()), pga);
+        }
+    }
+
+    #[test]
+    fn mode_round_trip() {
+        for mode in [Mode::Continuous, Mode::Signle] {
+            assert_eq!(mode.bits() & !0x0100, 0);
+            assert_eq!(Mode::from_bits(mode.bits()), mode);
+        }
+    }
+
+    #[test]
+    fn data_rate_round_trip() {
+        for dr in [
+            DataRate::SPS8, DataRate::SPS16, DataRate::SPS32, DataRate::SPS64,
+            DataRate::SPS128, DataRate::SPS250, DataRate::SPS475, DataRate::SPS860,
+        ] {
+            assert_eq!(dr.bits() & !0x00E0, 0);
+            assert_eq!(DataRate::from_bits(dr.bits()), dr);
+        }
+    }
+
+    #[test]
+    fn comparator_round_trip() {
+        for cm in [ComparatorMode::Traditional, ComparatorMode::Window] {
+            assert_eq!(cm.bits() & !0x0010, 0);
+            assert_eq!(ComparatorMode::from_bits(cm.bits()), cm);
+        }
+        for cp in [ComparatorPolarity::ActiveLow, ComparatorPolarity::ActiveHigh] {
+            assert_eq!(cp.bits() & !0x0008, 0);
+            assert_eq!(ComparatorPolarity::from_bits(cp.bits()), cp);
+        }
+        for cl in [LatchingComparator::NonLatching, LatchingComparator::Latching] {
+            assert_eq!(cl.bits() & !0x0004, 0);
+            assert_eq!(LatchingComparator::from_bits(cl.bits()), cl);
+        }
+        for cq in [ComparatorQueue::AsserAfterOne, ComparatorQueue::AsserAfterTwo, ComparatorQueue::AsserAfterFour, ComparatorQueue::Disable] {
+            assert_eq!(cq.bits() & !0x0003, 0);
+            assert_eq!(ComparatorQueue::from_bits(cq.bits()), cq);
+        }
+    }
+
+    #[test]
+    fn read_single_voltage() {
+        let expectations = [
+            Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER, 0xC5, 0x83], vec![0x45, 0x83]),
+            Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER], vec![0xC5, 0x83]),
+            Transaction::write_read(ADDRESS, vec![CONVERSION_REGISTER], vec![0x40, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+
+        let voltage = block_on(ads.read_single_voltage(&mut i2c, Some(InputMultiplexer::AIN0GND))).unwrap();
+        assert!((voltage - 1.024).abs() < 1e-6);
+        i2c.done();
+    }
+
+    #[test]
+    fn read_single_voltage_bus_error() {
+        let expectations = [
+            Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER, 0x85, 0x83], vec![0x05, 0x83]).with_error(ErrorKind::Other),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+
+        let result = block_on(ads.read_single_voltage(&mut i2c, None));
+        assert!(matches!(result, Err(ADSError::I2CError(ErrorKind::Other))));
+        i2c.done();
+    }
+
+    #[test]
+    fn read_single_voltage_config_mismatch() {
+        let expectations = [
+            Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER, 0x85, 0x83], vec![0x05, 0x03]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+
+        let result = block_on(ads.read_single_voltage(&mut i2c, None));
+        assert!(matches!(result, Err(ADSError::ConfigMismatch)));
+        i2c.done();
+    }
+
+    #[test]
+    fn read_single_voltage_timeout() {
+        let config = ADS111xConfig::default().dr(DataRate::SPS860);
+        let mut expectations = vec![
+            Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER, 0x85, 0xE3], vec![0x05, 0xE3]),
+        ];
+        for _ in 0..=CONVERSION_POLL_RETRIES {
+            expectations.push(Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER], vec![0x05, 0xE3]));
+        }
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, config).unwrap();
+
+        let result = block_on(ads.read_single_voltage(&mut i2c, None));
+        assert!(matches!(result, Err(ADSError::ConversionTimeout)));
+        i2c.done();
+    }
+
+    #[test]
+    fn read_voltage_12_bit() {
+        let expectations = [
+            Transaction::write_read(ADDRESS, vec![CONVERSION_REGISTER], vec![0x7F, 0xF0]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new_variant(ADDRESS, ADS111xConfig::default(), Variant::ADS1015).unwrap();
+
+        let voltage = block_on(ads.read_voltage(&mut i2c)).unwrap();
+        assert!((voltage - 2.047).abs() < 1e-6);
+        i2c.done();
+    }
+
+    /// Single shot conversion on AIN0GND at 128 SPS, config_hi selects the PGA
+    pub(super) fn single_conversion(config_hi: u8, code: [u8; 2]) -> [Transaction; 3] {
+        [
+            Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER, config_hi, 0x83], vec![config_hi & 0x7F, 0x83]),
+            Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER], vec![config_hi, 0x83]),
+            Transaction::write_read(ADDRESS, vec![CONVERSION_REGISTER], code.to_vec()),
+        ]
+    }
+
+    #[test]
+    fn autorange_jumps_to_coarsest_on_clip() {
+        let mut expectations = vec![];
+        expectations.extend(single_conversion(0xCB, [0x7F, 0xFF]));
+        expectations.extend(single_conversion(0xC1, [0x20, 0x00]));
+        expectations.extend(single_conversion(0xC5, [0x60, 0x00]));
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default().pga(ProgramableGainAmplifier::V4_096)).unwrap();
+
+        let (voltage, pga) = block_on(ads.read_single_voltage_autorange(&mut i2c, Some(InputMultiplexer::AIN0GND), Some(ProgramableGainAmplifier::V0_256), 0.1)).unwrap();
+        assert!((voltage - 1.536).abs() < 1e-6);
+        assert_eq!(pga, ProgramableGainAmplifier::V2_048);
+        assert_eq!(ads.config.mux, InputMultiplexer::AIN0AIN1);
+        assert_eq!(ads.config.pga, ProgramableGainAmplifier::V4_096);
+        i2c.done();
+    }
+
+    #[test]
+    fn autorange_steps_up_to_tightest_range() {
+        let mut expectations = vec![];
+        expectations.extend(single_conversion(0xC1, [0x08, 0x00]));
+        expectations.extend(single_conversion(0xC9, [0x60, 0x00]));
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+
+        let (voltage, pga) = block_on(ads.read_single_voltage_autorange(&mut i2c, Some(InputMultiplexer::AIN0GND), None, 0.1)).unwrap();
+        assert!((voltage - 0.384).abs() < 1e-6);
+        assert_eq!(pga, ProgramableGainAmplifier::V0_512);
+        assert_eq!(ads.config.pga, ProgramableGainAmplifier::V2_048);
+        i2c.done();
+    }
+
+    #[test]
+    fn autorange_hysteresis_keeps_range() {
+        // 0.95 V fits V1_024 but not within 90% of its full scale
+        let expectations = single_conversion(0xC5, [0x3B, 0x60]);
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+
+        let (voltage, pga) = block_on(ads.read_single_voltage_autorange(&mut i2c, Some(InputMultiplexer::AIN0GND), Some(ProgramableGainAmplifier::V2_048), 0.1)).unwrap();
+        assert!((voltage - 0.95).abs() < 1e-6);
+        assert_eq!(pga, ProgramableGainAmplifier::V2_048);
+        i2c.done();
+    }
+
+    #[test]
+    fn autorange_restores_settings_on_error() {
+        let expectations = [
+            Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER, 0xC1, 0x83], vec![0x41, 0x83]).with_error(ErrorKind::Other),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+
+        let result = block_on(ads.read_single_voltage_autorange(&mut i2c, Some(InputMultiplexer::AIN0GND), None, 0.1));
+        assert!(matches!(result, Err(ADSError::I2CError(ErrorKind::Other))));
+        assert_eq!(ads.config.mux, InputMultiplexer::AIN0AIN1);
+        assert_eq!(ads.config.pga, ProgramableGainAmplifier::V2_048);
+        i2c.done();
+    }
+
+    #[test]
+    fn read_single_voltage_on_ready() {
+        use embedded_hal_mock::eh1::digital::{Mock as PinMock, State, Transaction as PinTransaction};
+
+        let expectations = [
+            // Hi_thresh MSB set, Lo_thresh MSB cleared, queue enabled
+            Transaction::write(ADDRESS, vec![HI_THRESH_REGISTER, 0x80, 0x00]),
+            Transaction::write(ADDRESS, vec![LO_THRESH_REGISTER, 0x00, 0x00]),
+            Transaction::write(ADDRESS, vec![CONFIG_REGISTER, 0x45, 0x80]),
+            Transaction::write(ADDRESS, vec![CONFIG_REGISTER, 0xC5, 0x80]),
+            Transaction::write_read(ADDRESS, vec![CONVERSION_REGISTER], vec![0x20, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        // single shot and active low, the pin is awaited low instead of polling the config register
+        let mut ready = PinMock::new(&[PinTransaction::wait_for_state(State::Low)]);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default().mux(InputMultiplexer::AIN0GND)).unwrap();
+
+        block_on(ads.enable_conversion_ready_pin(&mut i2c)).unwrap();
+        let voltage = block_on(ads.read_single_voltage_on_ready(&mut i2c, &mut ready, None)).unwrap();
+        assert!((voltage - 0.512).abs() < 1e-6);
+        i2c.done();
+        ready.done();
+    }
+
+    #[test]
+    fn verify_config() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![CONFIG_REGISTER, 0x05, 0x83]),
+            Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER], vec![0x85, 0x83]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+
+        block_on(ads.verify_config(&mut i2c, None)).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn verify_config_mismatch() {
+        // the comparator queue bits did not take
+        let expectations = [
+            Transaction::write(ADDRESS, vec![CONFIG_REGISTER, 0x03, 0x80]),
+            Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER], vec![0x85, 0x83]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+        let config = ADS111xConfig::default().pga(ProgramableGainAmplifier::V4_096).comp_que(ComparatorQueue::AsserAfterOne);
+
+        let result = block_on(ads.verify_config(&mut i2c, Some(config)));
+        assert!(matches!(result, Err(ADSError::ConfigMismatch)));
+        i2c.done();
+    }
+
+    #[test]
+    fn dump_registers() {
+        let expectations = [
+            Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER], vec![0x85, 0x83]),
+            Transaction::write_read(ADDRESS, vec![CONVERSION_REGISTER], vec![0x40, 0x00]),
+            Transaction::write_read(ADDRESS, vec![LO_THRESH_REGISTER], vec![0x80, 0x00]),
+            Transaction::write_read(ADDRESS, vec![HI_THRESH_REGISTER], vec![0x7F, 0xFF]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+
+        let dump = block_on(ads.dump_registers(&mut i2c)).unwrap();
+        assert_eq!(dump.config.bits(), 0x0583);
+        assert!(!dump.config.is_converting());
+        assert_eq!(dump.conversion.code, 16384);
+        assert_eq!(dump.lo_thresh, i16::MIN);
+        assert_eq!(dump.hi_thresh, i16::MAX);
+
+        let text = format!("{}", dump);
+        assert!(text.contains("config:     0x0583 converting=false"));
+        assert!(text.contains("conversion: 16384 (1024000 uV)"));
+        assert!(text.ends_with("hi_thresh:  0x7fff"));
+        i2c.done();
+    }
+
+    #[test]
+    fn threshold_codes_per_pga() {
+        for pga in ProgramableGainAmplifier::RANGES {
+            let fs = pga.full_scale();
+            assert_eq!(pga.volts_to_code(fs / 2f32), Some(16384));
+            assert_eq!(pga.volts_to_code(-fs / 4f32), Some(-8192));
+            assert_eq!(pga.volts_to_code(-fs), Some(i16::MIN));
+            assert_eq!(pga.volts_to_code(fs * 1.01), None);
+            assert_eq!(pga.volts_to_code(-fs * 1.01), None);
+        }
+    }
+
+    #[test]
+    fn set_window_alarm() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![LO_THRESH_REGISTER, 0x20, 0x00]),
+            Transaction::write(ADDRESS, vec![HI_THRESH_REGISTER, 0x60, 0x00]),
+            Transaction::write(ADDRESS, vec![CONFIG_REGISTER, 0x03, 0x95]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default().pga(ProgramableGainAmplifier::V4_096)).unwrap();
+
+        block_on(ads.set_window_alarm(&mut i2c, 1.024, 3.072, ComparatorQueue::AsserAfterTwo)).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn set_window_alarm_out_of_range() {
+        let mut i2c = Mock::new(&[]);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default().pga(ProgramableGainAmplifier::V4_096)).unwrap();
+
+        for (low, high, queue) in [
+            (1f32, 5f32, ComparatorQueue::AsserAfterOne),
+            (-5f32, 1f32, ComparatorQueue::AsserAfterOne),
+            (2f32, 1f32, ComparatorQueue::AsserAfterOne),
+            (1f32, 2f32, ComparatorQueue::Disable),
+        ] {
+            let result = block_on(ads.set_window_alarm(&mut i2c, low, high, queue));
+            assert!(matches!(result, Err(ADSError::ThresholdOutOfRange)));
+        }
+        i2c.done();
+    }
+
+    #[test]
+    fn alert_pin_modes_exclude_each_other() {
+        use embedded_hal_mock::eh1::digital::Mock as PinMock;
+
+        let expectations = [
+            Transaction::write(ADDRESS, vec![HI_THRESH_REGISTER, 0x80, 0x00]),
+            Transaction::write(ADDRESS, vec![LO_THRESH_REGISTER, 0x00, 0x00]),
+            Transaction::write(ADDRESS, vec![CONFIG_REGISTER, 0x05, 0x80]),
+            Transaction::write(ADDRESS, vec![CONFIG_REGISTER, 0x05, 0x83]),
+            Transaction::write(ADDRESS, vec![LO_THRESH_REGISTER, 0x20, 0x00]),
+            Transaction::write(ADDRESS, vec![HI_THRESH_REGISTER, 0x60, 0x00]),
+            Transaction::write(ADDRESS, vec![CONFIG_REGISTER, 0x05, 0x94]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut ready = PinMock::new(&[]);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+
+        block_on(ads.enable_conversion_ready_pin(&mut i2c)).unwrap();
+        let result = block_on(ads.set_window_alarm(&mut i2c, 0.512, 1.536, ComparatorQueue::AsserAfterOne));
+        assert!(matches!(result, Err(ADSError::ConfigMismatch)));
+
+        block_on(ads.disable_alert_pin(&mut i2c)).unwrap();
+        block_on(ads.set_window_alarm(&mut i2c, 0.512, 1.536, ComparatorQueue::AsserAfterOne)).unwrap();
+        let result: Result<(), ADSError<ErrorKind>> = block_on(ads.wait_conversion_ready(&mut ready));
+        assert!(matches!(result, Err(ADSError::ConfigMismatch)));
+        let result = block_on(ads.enable_conversion_ready_pin(&mut i2c));
+        assert!(matches!(result, Err(ADSError::ConfigMismatch)));
+        i2c.done();
+        ready.done();
+    }
+
+    #[test]
+    fn alert_asserted_and_clear() {
+        use embedded_hal_mock::eh1::digital::{Mock as PinMock, State, Transaction as PinTransaction};
+
+        let expectations = [
+            Transaction::write_read(ADDRESS, vec![CONVERSION_REGISTER], vec![0x70, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut alert = PinMock::new(&[
+            PinTransaction::get(State::Low),
+            PinTransaction::get(State::High),
+            PinTransaction::get(State::High),
+        ]);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+
+        // active low by default
+        assert!(ads.alert_asserted::<(), _>(&mut alert).unwrap());
+        assert!(!ads.alert_asserted::<(), _>(&mut alert).unwrap());
+        ads.config.comp_pol = ComparatorPolarity::ActiveHigh;
+        assert!(ads.alert_asserted::<(), _>(&mut alert).unwrap());
+
+        let voltage = block_on(ads.clear_alert(&mut i2c)).unwrap();
+        assert!((voltage - 1.792).abs() < 1e-6);
+        i2c.done();
+        alert.done();
+    }
+
+    #[test]
+    fn stream_continuous_on_ready() {
+        use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+        use embassy_sync::channel::Channel;
+        use embedded_hal_mock::eh1::digital::{Edge, Mock as PinMock, Transaction as PinTransaction};
+
+        let expectations = [
+            Transaction::write_read(ADDRESS, vec![CONVERSION_REGISTER], vec![0x40, 0x00]),
+            Transaction::write_read(ADDRESS, vec![CONVERSION_REGISTER], vec![0x20, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut ready = PinMock::new(&[
+            PinTransaction::wait_for_edge(Edge::Falling),
+            PinTransaction::wait_for_edge(Edge::Falling),
+        ]);
+        let channel: Channel<NoopRawMutex, Sample, 1> = Channel::new();
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default().mode(Mode::Continuous)).unwrap();
+
+        let report = block_on(ads.stream_continuous_on_ready(&mut i2c, &mut ready, channel.sender(), 2)).unwrap();
+        assert_eq!(report, StreamReport { dropped: 1, missed: 0 });
+        assert!((channel.try_receive().unwrap().voltage - 1.024).abs() < 1e-4);
+        i2c.done();
+        ready.done();
+    }
+
+    #[test]
+    fn variant_rejects_missing_features() {
+        let pga = ADS111xConfig::default().pga(ProgramableGainAmplifier::V4_096);
+        assert!(matches!(ADS111x::new_variant(ADDRESS, pga, Variant::ADS1113), Err(ADSError::UnsupportedByVariant)));
+        assert!(ADS111x::new_variant(ADDRESS, pga, Variant::ADS1114).is_ok());
+
+        let mux = ADS111xConfig::default().mux(InputMultiplexer::AIN2GND);
+        assert!(matches!(ADS111x::new_variant(ADDRESS, mux, Variant::ADS1014), Err(ADSError::UnsupportedByVariant)));
+        assert!(ADS111x::new_variant(ADDRESS, mux, Variant::ADS1015).is_ok());
+    }
+
+    #[test]
+    fn raw_reading_fixed_point() {
+        let raw = RawReading{ code: 16384, pga: ProgramableGainAmplifier::V2_048, variant: Variant::ADS1115 };
+        assert_eq!(raw.millivolts(), I16F16::from_num(1024));
+        assert_eq!(raw.microvolts(), 1_024_000);
+
+        let raw = RawReading{ code: -2048, pga: ProgramableGainAmplifier::V6_144, variant: Variant::ADS1015 };
+        assert_eq!(raw.millivolts(), I16F16::from_num(-6144));
+        assert!(raw.is_saturated());
+    }
+
+    #[test]
+    fn wrong_address() {
+        assert!(matches!(ADS111x::new(0x50, ADS111xConfig::default()), Err(ADSError::WrongAddress)));
+    }
+}
@@ -0,0 +1,143 @@
+use embedded_hal_async::i2c::I2c;
+use crate::iox::analog_input::ads1115::{ADS111x, ADSError, InputMultiplexer, ProgramableGainAmplifier};
+
+static CALIBRATION_SAMPLES: u8 = 8;
+
+/// Linear correction of a reading, corrected = measured * gain + offset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration{
+    pub offset: f32,
+    pub gain: f32,
+}
+
+impl Calibration {
+    pub const fn new(offset: f32, gain: f32) -> Self {
+        Calibration {
+            offset,
+            gain,
+        }
+    }
+
+    /// Computes the coefficients from two (measured, reference) voltage pairs
+    /// Returns None when both measurements are equal
+    pub fn from_two_points(low: (f32, f32), high: (f32, f32)) -> Option<Self> {
+        let (measured_low, reference_low) = low;
+        let (measured_high, reference_high) = high;
+
+        if measured_high == measured_low {
+            return None;
+        }
+
+        let gain = (reference_high - reference_low) / (measured_high - measured_low);
+        let offset = reference_low - measured_low * gain;
+
+        Some(Calibration::new(offset, gain))
+    }
+
+    pub fn apply(&self, voltage: f32) -> f32 {
+        voltage * self.gain + self.offset
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration::new(0f32, 1f32)
+    }
+}
+
+/// On-device two point calibration of one input
+/// Apply the low reference (e.g. shorted input) and call capture_low, then apply the
+/// high reference and call capture_high. finish computes the coefficients and stores
+/// them in the device, the returned value can be persisted by the caller
+pub(crate) struct TwoPointCalibration {
+    mux: InputMultiplexer,
+    pga: ProgramableGainAmplifier,
+    low: Option<(f32, f32)>,
+    high: Option<(f32, f32)>,
+}
+
+impl TwoPointCalibration {
+    pub(crate) fn new(mux: InputMultiplexer, pga: ProgramableGainAmplifier) -> Self {
+        TwoPointCalibration {
+            mux,
+            pga,
+            low: None,
+            high: None,
+        }
+    }
+
+    /// Measures the input while the low reference voltage is applied
+    pub(crate) async fn capture_low<I2C, E>(&mut self, ads: &mut ADS111x, i2c: &mut I2C, reference: f32) -> Result<f32, ADSError<E>> where I2C: I2c<Error = E> {
+        let measured = self.capture(ads, i2c).await?;
+        self.low = Some((measured, reference));
+        Ok(measured)
+    }
+
+    /// Measures the input while the high reference voltage is applied
+    pub(crate) async fn capture_high<I2C, E>(&mut self, ads: &mut ADS111x, i2c: &mut I2C, reference: f32) -> Result<f32, ADSError<E>> where I2C: I2c<Error = E> {
+        let measured = self.capture(ads, i2c).await?;
+        self.high = Some((measured, reference));
+        Ok(measured)
+    }
+
+    pub(crate) fn finish<E>(self, ads: &mut ADS111x) -> Result<Calibration, ADSError<E>> {
+        let (Some(low), Some(high)) = (self.low, self.high) else {
+            return Err(ADSError::CalibrationFailed);
+        };
+        let calibration = Calibration::from_two_points(low, high).ok_or(ADSError::CalibrationFailed)?;
+
+        ads.set_calibration(self.mux, self.pga, calibration)?;
+        Ok(calibration)
+    }
+
+    /// Averages several uncalibrated conversions
+    /// The device's mux and pga are restored afterwards, also when a conversion fails
+    async fn capture<I2C, E>(&self, ads: &mut ADS111x, i2c: &mut I2C) -> Result<f32, ADSError<E>> where I2C: I2c<Error = E> {
+        let (mux, pga) = (ads.config.mux, ads.config.pga);
+        ads.config.pga = self.pga;
+
+        let sum = self.sum_samples(ads, i2c).await;
+        ads.config.mux = mux;
+        ads.config.pga = pga;
+
+        Ok(sum? / f32::from(CALIBRATION_SAMPLES))
+    }
+
+    async fn sum_samples<I2C, E>(&self, ads: &mut ADS111x, i2c: &mut I2C) -> Result<f32, ADSError<E>> where I2C: I2c<Error = E> {
+        let mut sum = 0f32;
+        for _ in 0..CALIBRATION_SAMPLES {
+            sum += ads.read_single_uncalibrated_voltage(i2c, Some(self.mux)).await?;
+        }
+
+        Ok(sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_hal_1::i2c::ErrorKind;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+    use crate::iox::analog_input::ads1115::{ADS111xConfig, CONFIG_REGISTER};
+    use crate::iox::analog_input::ads1115::tests::ADDRESS;
+
+    #[test]
+    fn capture_restores_settings() {
+        let expectations = [
+            Transaction::write_read(ADDRESS, vec![CONFIG_REGISTER, 0xC3, 0x83], vec![0x43, 0x83]).with_error(ErrorKind::Other),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+        let mut calibration = TwoPointCalibration::new(InputMultiplexer::AIN0GND, ProgramableGainAmplifier::V4_096);
+
+        let result = block_on(calibration.capture_low(&mut ads, &mut i2c, 0f32));
+        assert!(matches!(result, Err(ADSError::I2CError(ErrorKind::Other))));
+        assert_eq!(ads.config.mux, InputMultiplexer::AIN0AIN1);
+        assert_eq!(ads.config.pga, ProgramableGainAmplifier::V2_048);
+        // finish shares the error type with the capture methods
+        let result: Result<_, ADSError<ErrorKind>> = calibration.finish(&mut ads);
+        assert!(matches!(result, Err(ADSError::CalibrationFailed)));
+        i2c.done();
+    }
+}
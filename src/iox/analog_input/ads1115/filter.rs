@@ -0,0 +1,184 @@
+use embedded_hal_async::i2c::I2c;
+use crate::iox::analog_input::ads1115::{ADS111x, ADSError, InputMultiplexer};
+
+pub const MAX_FILTER_SAMPLES: u8 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Reduction{
+    #[default]
+    Mean,
+    Median,
+}
+
+/// An input with its own filter chain
+/// Every read takes samples conversions, reduces them by mean or median
+/// and optionally feeds the result through an exponential moving average
+#[derive(Debug, Clone, Copy)]
+pub struct FilteredChannel{
+    mux: InputMultiplexer,
+    samples: u8,
+    reduction: Reduction,
+    ema_alpha: Option<f32>,
+    ema: Option<f32>,
+}
+
+impl FilteredChannel {
+    pub fn new(mux: InputMultiplexer) -> Self {
+        FilteredChannel {
+            mux,
+            samples: 1,
+            reduction: Reduction::Mean,
+            ema_alpha: None,
+            ema: None,
+        }
+    }
+
+    /// Averages samples conversions per reading
+    pub fn oversample(mut self, samples: u8) -> Self {
+        self.samples = samples.clamp(1, MAX_FILTER_SAMPLES);
+        self.reduction = Reduction::Mean;
+        self
+    }
+
+    /// Takes the median of samples conversions per reading to reject spikes
+    pub fn median(mut self, samples: u8) -> Self {
+        self.samples = samples.clamp(1, MAX_FILTER_SAMPLES);
+        self.reduction = Reduction::Median;
+        self
+    }
+
+    /// Smooths consecutive readings, alpha is the weight of the newest reading (0, 1]
+    pub fn ema(mut self, alpha: f32) -> Self {
+        self.ema_alpha = Some(alpha.clamp(f32::EPSILON, 1f32));
+        self
+    }
+
+    /// Forgets the moving average, the next reading starts it again
+    pub fn reset(&mut self) {
+        self.ema = None;
+    }
+
+    /// Readings per second this channel can deliver with the device's data rate
+    pub fn effective_rate(&self, ads: &ADS111x) -> f32 {
+        f32::from(ads.config.dr.samples_per_second(ads.variant)) / f32::from(self.samples)
+    }
+
+    pub async fn read<I2C, E>(&mut self, ads: &mut ADS111x, i2c: &mut I2C) -> Result<f32, ADSError<E>> where I2C: I2c<Error = E> {
+        let mut buf = [0f32; MAX_FILTER_SAMPLES as usize];
+        let buf = &mut buf[..self.samples as usize];
+        for sample in buf.iter_mut() {
+            *sample = ads.read_single_voltage(i2c, Some(self.mux)).await?;
+        }
+
+        let value = match self.reduction {
+            Reduction::Mean => buf.iter().sum::<f32>() / f32::from(self.samples),
+            Reduction::Median => median(buf),
+        };
+
+        Ok(match (self.ema_alpha, self.ema) {
+            (Some(alpha), Some(ema)) => {
+                let ema = alpha * value + (1f32 - alpha) * ema;
+                self.ema = Some(ema);
+                ema
+            }
+            (Some(_), None) => {
+                self.ema = Some(value);
+                value
+            }
+            (None, _) => value,
+        })
+    }
+}
+
+fn median(buf: &mut [f32]) -> f32 {
+    for i in 1..buf.len() {
+        let mut j = i;
+        while j > 0 && buf[j - 1] > buf[j] {
+            buf.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+
+    let mid = buf.len() / 2;
+    match buf.len() % 2 {
+        0 => (buf[mid - 1] + buf[mid]) / 2f32,
+        _ => buf[mid],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_hal_mock::eh1::i2c::Mock;
+    use crate::iox::analog_input::ads1115::{ADS111xConfig, DataRate, Variant};
+    use crate::iox::analog_input::ads1115::tests::{single_conversion, ADDRESS};
+
+    /// AIN0GND at the default 2.048 V range
+    const CONFIG_HI: u8 = 0xC5;
+
+    #[test]
+    fn median_rejects_spike() {
+        assert_eq!(median(&mut [1.0, 1.1, 9.0, 0.9, 1.0]), 1.0);
+        assert_eq!(median(&mut [-7.0]), -7.0);
+    }
+
+    #[test]
+    fn median_even_length() {
+        assert_eq!(median(&mut [4.0, 1.0, 3.0, 2.0]), 2.5);
+        assert_eq!(median(&mut [1.0, 1.0, 30.0, 1.0]), 1.0);
+    }
+
+    #[test]
+    fn read_median_ignores_spike() {
+        let mut expectations = vec![];
+        expectations.extend(single_conversion(CONFIG_HI, [0x40, 0x00]));
+        expectations.extend(single_conversion(CONFIG_HI, [0x7F, 0xFF]));
+        expectations.extend(single_conversion(CONFIG_HI, [0x40, 0x00]));
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+        let mut channel = FilteredChannel::new(InputMultiplexer::AIN0GND).median(3);
+
+        let voltage = block_on(channel.read(&mut ads, &mut i2c)).unwrap();
+        assert!((voltage - 1.024).abs() < 1e-6);
+        i2c.done();
+    }
+
+    #[test]
+    fn ema_converges() {
+        let mut expectations = vec![];
+        expectations.extend(single_conversion(CONFIG_HI, [0x00, 0x00]));
+        for _ in 0..4 {
+            expectations.extend(single_conversion(CONFIG_HI, [0x40, 0x00]));
+        }
+        let mut i2c = Mock::new(&expectations);
+        let mut ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+        let mut channel = FilteredChannel::new(InputMultiplexer::AIN0GND).ema(0.5);
+
+        // the first reading seeds the average, every further one halves the distance to the input
+        let mut expected = 0f32;
+        assert_eq!(block_on(channel.read(&mut ads, &mut i2c)).unwrap(), expected);
+        for _ in 0..3 {
+            expected += (1.024 - expected) / 2f32;
+            let voltage = block_on(channel.read(&mut ads, &mut i2c)).unwrap();
+            assert!((voltage - expected).abs() < 1e-6);
+        }
+        assert!((expected - 0.896).abs() < 1e-6);
+
+        channel.reset();
+        let voltage = block_on(channel.read(&mut ads, &mut i2c)).unwrap();
+        assert!((voltage - 1.024).abs() < 1e-6);
+        i2c.done();
+    }
+
+    #[test]
+    fn effective_rate_divides_by_samples() {
+        let ads = ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap();
+        assert_eq!(FilteredChannel::new(InputMultiplexer::AIN0GND).effective_rate(&ads), 128f32);
+        assert_eq!(FilteredChannel::new(InputMultiplexer::AIN0GND).oversample(4).effective_rate(&ads), 32f32);
+        assert_eq!(FilteredChannel::new(InputMultiplexer::AIN0GND).median(40).effective_rate(&ads), 8f32);
+
+        let ads = ADS111x::new_variant(ADDRESS, ADS111xConfig::default().dr(DataRate::SPS860), Variant::ADS1015).unwrap();
+        assert_eq!(FilteredChannel::new(InputMultiplexer::AIN0GND).oversample(8).effective_rate(&ads), 412.5);
+    }
+}
@@ -0,0 +1,181 @@
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
+use embedded_hal_async::i2c::I2c;
+use crate::iox::analog_input::ads1115::{ADS111x, ADSError, DataRate, InputMultiplexer, ProgramableGainAmplifier};
+
+/// One input of the scan list
+/// device is the index into the device slice handed to the sequencer
+#[derive(Debug, Clone, Copy)]
+pub struct ScanEntry{
+    pub device: usize,
+    pub mux: InputMultiplexer,
+    pub pga: ProgramableGainAmplifier,
+    pub dr: DataRate,
+}
+
+impl ScanEntry {
+    pub const fn new(device: usize, mux: InputMultiplexer, pga: ProgramableGainAmplifier, dr: DataRate) -> Self {
+        ScanEntry {
+            device,
+            mux,
+            pga,
+            dr,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScanReading{
+    pub voltage: f32,
+    pub timestamp: Instant,
+}
+
+impl ScanReading {
+    pub fn age(&self) -> Duration {
+        self.timestamp.elapsed()
+    }
+}
+
+/// Latest reading per scan entry, shared between the task owning the bus and any reader
+pub(crate) struct ScanTable<const N: usize> {
+    readings: Mutex<CriticalSectionRawMutex, RefCell<[Option<ScanReading>; N]>>,
+}
+
+impl<const N: usize> ScanTable<N> {
+    pub(crate) const fn new() -> Self {
+        ScanTable {
+            readings: Mutex::new(RefCell::new([None; N])),
+        }
+    }
+
+    pub(crate) fn latest(&self, index: usize) -> Option<ScanReading> {
+        self.readings.lock(|r| r.borrow().get(index).copied().flatten())
+    }
+
+    /// Returns the latest voltage only if it is not older than max_age
+    pub(crate) fn latest_voltage(&self, index: usize, max_age: Duration) -> Option<f32> {
+        self.latest(index)
+            .filter(|r| r.age() <= max_age)
+            .map(|r| r.voltage)
+    }
+
+    fn update(&self, index: usize, reading: ScanReading) {
+        self.readings.lock(|r| r.borrow_mut()[index] = Some(reading));
+    }
+}
+
+/// Cycles round-robin through a list of inputs spread over several ADS111x devices
+/// The devices' pga and dr are only changed for the duration of a conversion, so direct
+/// callers, filters and calibration lookups on the same device keep seeing their own settings
+pub(crate) struct ScanSequencer<'a, const N: usize> {
+    entries: [ScanEntry; N],
+    table: &'a ScanTable<N>,
+    next: usize,
+    checked_devices: Option<usize>,
+}
+
+impl<'a, const N: usize> ScanSequencer<'a, N> {
+    pub(crate) fn new(entries: [ScanEntry; N], table: &'a ScanTable<N>) -> Self {
+        ScanSequencer {
+            entries,
+            table,
+            next: 0,
+            checked_devices: None,
+        }
+    }
+
+    /// Checks that every entry points into the device slice, only repeated when its length changes
+    fn check_entries<E>(&mut self, devices: &[ADS111x]) -> Result<(), ADSError<E>> {
+        if self.checked_devices == Some(devices.len()) {
+            return Ok(());
+        }
+        if self.entries.iter().any(|e| e.device >= devices.len()) {
+            return Err(ADSError::InvalidScanEntry);
+        }
+
+        self.checked_devices = Some(devices.len());
+        Ok(())
+    }
+
+    /// Converts the next entry of the scan list and stores it in the table
+    /// The sequencer advances even on error so a faulted input does not stall the others,
+    /// its table entry just keeps aging
+    /// Fails with InvalidScanEntry without converting anything if an entry points past devices
+    pub(crate) async fn step<I2C, E>(&mut self, i2c: &mut I2C, devices: &mut [ADS111x]) -> Result<(), ADSError<E>> where I2C: I2c<Error = E> {
+        self.check_entries(devices)?;
+
+        let index = self.next;
+        self.next = (self.next + 1) % N;
+
+        let entry = self.entries[index];
+        let device = &mut devices[entry.device];
+        let (mux, pga, dr) = (device.config.mux, device.config.pga, device.config.dr);
+        device.config.pga = entry.pga;
+        device.config.dr = entry.dr;
+
+        let voltage = device.read_single_voltage(i2c, Some(entry.mux)).await;
+        device.config.mux = mux;
+        device.config.pga = pga;
+        device.config.dr = dr;
+
+        self.table.update(index, ScanReading { voltage: voltage?, timestamp: Instant::now() });
+
+        Ok(())
+    }
+
+    /// Runs one full pass over the scan list, returns the number of entries that failed
+    pub(crate) async fn scan_all<I2C, E>(&mut self, i2c: &mut I2C, devices: &mut [ADS111x]) -> usize where I2C: I2c<Error = E> {
+        let mut failed = 0;
+        for _ in 0..N {
+            if self.step(i2c, devices).await.is_err() {
+                failed += 1;
+            }
+        }
+        failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_hal_mock::eh1::i2c::Mock;
+    use crate::iox::analog_input::ads1115::ADS111xConfig;
+    use crate::iox::analog_input::ads1115::tests::{single_conversion, ADDRESS};
+
+    #[test]
+    fn step_restores_device_settings() {
+        // AIN0GND at 4.096 V
+        let expectations = single_conversion(0xC3, [0x40, 0x00]);
+        let mut i2c = Mock::new(&expectations);
+        let mut devices = [ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap()];
+        let table = ScanTable::<1>::new();
+        let mut scan = ScanSequencer::new([
+            ScanEntry::new(0, InputMultiplexer::AIN0GND, ProgramableGainAmplifier::V4_096, DataRate::SPS128),
+        ], &table);
+
+        block_on(scan.step(&mut i2c, &mut devices)).unwrap();
+        assert!((table.latest(0).unwrap().voltage - 2.048).abs() < 1e-6);
+        assert_eq!(devices[0].config.mux, InputMultiplexer::AIN0AIN1);
+        assert_eq!(devices[0].config.pga, ProgramableGainAmplifier::V2_048);
+        assert_eq!(devices[0].config.dr, DataRate::SPS128);
+        i2c.done();
+    }
+
+    #[test]
+    fn step_rejects_invalid_entry() {
+        let mut i2c = Mock::new(&[]);
+        let mut devices = [ADS111x::new(ADDRESS, ADS111xConfig::default()).unwrap()];
+        let table = ScanTable::<2>::new();
+        let mut scan = ScanSequencer::new([
+            ScanEntry::new(0, InputMultiplexer::AIN0GND, ProgramableGainAmplifier::V4_096, DataRate::SPS128),
+            ScanEntry::new(1, InputMultiplexer::AIN1GND, ProgramableGainAmplifier::V4_096, DataRate::SPS128),
+        ], &table);
+
+        let result = block_on(scan.step(&mut i2c, &mut devices));
+        assert!(matches!(result, Err(ADSError::InvalidScanEntry)));
+        i2c.done();
+    }
+}
@@ -1,14 +1,22 @@
 #![no_std]
 
 use core::fmt;
+use core::ops::{Add, AddAssign, Neg, Sub, SubAssign};
 use defmt::export::u8;
-use embassy_time::Timer;
+use embassy_time::{Duration, Instant, Timer};
 use embedded_hal_async::i2c::I2c;
+use fixed::types::{I5F11, U2F14};
 use ux::i24;
 
+pub(crate) mod level;
+
 #[derive(Debug)]
 pub enum FDCError<E>{
     MeasurementNotComplete,
+    Timeout,
+    OutOfRange,
+    InvalidChannelPair,
+    UnknownDevice { manufacturer: u16, device: u16 },
     I2CError(E)
 }
 
@@ -21,6 +29,10 @@ pub enum OutputRate {
 }
 
 static CAPDAC_MAX: u8 = 0x1F;
+static MANUFACTURER_ID: u16 = 0x5449;
+static DEVICE_ID: u16 = 0x1004;
+static DEFAULT_TIMEOUT: Duration = Duration::from_millis(50);
+static POLL_BACKOFF_START: Duration = Duration::from_micros(500);
 
 #[derive(Copy, Clone, Debug)]
 pub enum Channel {
@@ -32,6 +44,19 @@ pub enum Channel {
     DISABLED
 }
 
+impl Channel {
+    /// Position of a CIN input, None for CAPDAC and DISABLED
+    pub(crate) fn input_index(&self) -> Option<u8> {
+        match self {
+            Channel::CIN1 => Some(0),
+            Channel::CIN2 => Some(1),
+            Channel::CIN3 => Some(2),
+            Channel::CIN4 => Some(3),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum Measurement {
     Measurement1,
@@ -40,6 +65,13 @@ pub enum Measurement {
     Measurement4,
 }
 
+static MEASUREMENTS: [Measurement; 4] = [
+    Measurement::Measurement1,
+    Measurement::Measurement2,
+    Measurement::Measurement3,
+    Measurement::Measurement4,
+];
+
 #[derive(Copy, Clone, Debug)]
 pub enum Register {
     Measurement1MSB,
@@ -97,10 +129,151 @@ impl Register {
     }
 }
 
+impl Register {
+    pub(crate) fn offset_calibration(channel: Channel) -> Option<Register> {
+        match channel {
+            Channel::CIN1 => Some(Register::OffsetCalCIN1),
+            Channel::CIN2 => Some(Register::OffsetCalCIN2),
+            Channel::CIN3 => Some(Register::OffsetCalCIN3),
+            Channel::CIN4 => Some(Register::OffsetCalCIN4),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn gain_calibration(channel: Channel) -> Option<Register> {
+        match channel {
+            Channel::CIN1 => Some(Register::GainCalCIN1),
+            Channel::CIN2 => Some(Register::GainCalCIN2),
+            Channel::CIN3 => Some(Register::GainCalCIN3),
+            Channel::CIN4 => Some(Register::GainCalCIN4),
+            _ => None,
+        }
+    }
+}
+
 static FDC_REGISTER: u8 = 0x0C;
 
-static ATTOFARADS_UPPER_WORD:i32 = 488;
 static PICOFARADS_PER_CAPDAC: f32 = 3.125;
+static ATTOFARADS_PER_CAPDAC: i64 = 3_125_000;
+static ATTOFARADS_PER_PICOFARAD: i64 = 1_000_000;
+/// The 24 bit result is a fraction of +-16 pF, one LSB is 2^-19 pF
+static MEASUREMENT_LSB_PER_PICOFARAD: i64 = 1 << 19;
+
+/// Capacitance as an integer number of attofarads
+/// Sums and differences are exact, the conversion from a measurement rounds to the nearest aF,
+/// well below the 1.9 fF resolution of the device
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Capacitance(i64);
+
+impl Capacitance {
+    pub const ZERO: Capacitance = Capacitance(0);
+
+    pub const fn from_attofarads(af: i64) -> Self {
+        Capacitance(af)
+    }
+
+    pub const fn from_femtofarads(ff: i64) -> Self {
+        Capacitance(ff * 1000)
+    }
+
+    pub const fn from_picofarads(pf: i64) -> Self {
+        Capacitance(pf * ATTOFARADS_PER_PICOFARAD)
+    }
+
+    /// Converts a raw 24 bit result taken with the given CAPDAC setting
+    pub fn from_measurement(value: i24, capdac: u8) -> Self {
+        let value: i32 = value.into();
+        let af = (i64::from(value) * ATTOFARADS_PER_PICOFARAD + MEASUREMENT_LSB_PER_PICOFARAD / 2)
+            .div_euclid(MEASUREMENT_LSB_PER_PICOFARAD);
+
+        Capacitance(af + i64::from(capdac) * ATTOFARADS_PER_CAPDAC)
+    }
+
+    pub const fn attofarads(&self) -> i64 {
+        self.0
+    }
+
+    /// Truncated towards zero
+    pub const fn femtofarads(&self) -> i64 {
+        self.0 / 1000
+    }
+
+    /// Only the sub-picofarad remainder goes through a division, keeping the conversion in single precision
+    pub fn to_pf(&self) -> f32 {
+        let (sign, pf, af) = self.split_pf();
+        let pf = pf as f32 + af as f32 / ATTOFARADS_PER_PICOFARAD as f32;
+
+        if sign.is_empty() { pf } else { -pf }
+    }
+
+    pub const fn abs(&self) -> Self {
+        Capacitance(self.0.abs())
+    }
+
+    /// Sign, whole picofarads and the remaining attofarads of the magnitude
+    fn split_pf(&self) -> (&'static str, u64, u64) {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let af = self.0.unsigned_abs();
+        let per_pf = ATTOFARADS_PER_PICOFARAD as u64;
+        (sign, af / per_pf, af % per_pf)
+    }
+}
+
+impl Add for Capacitance {
+    type Output = Capacitance;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Capacitance(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Capacitance {
+    type Output = Capacitance;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Capacitance(self.0 - rhs.0)
+    }
+}
+
+impl Neg for Capacitance {
+    type Output = Capacitance;
+
+    fn neg(self) -> Self::Output {
+        Capacitance(-self.0)
+    }
+}
+
+impl AddAssign for Capacitance {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 += rhs.0;
+    }
+}
+
+impl SubAssign for Capacitance {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl fmt::Display for Capacitance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (sign, pf, af) = self.split_pf();
+        write!(f, "{}{}.{:06} pF", sign, pf, af)
+    }
+}
+
+impl fmt::Debug for Capacitance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl defmt::Format for Capacitance {
+    fn format(&self, f: defmt::Formatter) {
+        let (sign, pf, af) = self.split_pf();
+        defmt::write!(f, "{=str}{=u64}.{=u64:06} pF", sign, pf, af)
+    }
+}
 
 pub(crate) enum SuccessfulMeasurement {
     MeasurementInRange(MeasuredCapacitance),
@@ -121,14 +294,8 @@ impl MeasuredCapacitance {
         }
     }
 
-    pub(crate) fn to_weird(&self) -> f32 {
-        let val: i32 = self.value.into();
-
-        let mut cap = val * ATTOFARADS_UPPER_WORD;
-        cap /= 1000;
-        cap += self.capdac as i32 * 3125i32;
-
-        cap as f32
+    pub(crate) fn capacitance(&self) -> Capacitance {
+        Capacitance::from_measurement(self.value, self.capdac)
     }
 
     pub(crate) fn to_pf(&self) -> f32 {
@@ -248,16 +415,34 @@ impl FDCConfiguration {
         self
     }
 
+    pub(crate) fn initiate(&mut self, measurement: Measurement, initiate: bool) -> &mut Self {
+        match measurement {
+            Measurement::Measurement1 => self.initiate_measurement1(initiate),
+            Measurement::Measurement2 => self.initiate_measurement2(initiate),
+            Measurement::Measurement3 => self.initiate_measurement3(initiate),
+            Measurement::Measurement4 => self.initiate_measurement4(initiate),
+        }
+    }
+
     pub(crate) fn reset(&mut self, reset: bool) -> &mut Self {
         self.reset = reset;
         self
     }
 
     pub(crate) fn repeat(&mut self, repeat: bool) -> &mut Self {
-        self.reset = repeat;
+        self.repeat = repeat;
         self
     }
 
+    pub(crate) fn done(&self, measurement: Measurement) -> bool {
+        match measurement {
+            Measurement::Measurement1 => self.measurement1_done,
+            Measurement::Measurement2 => self.measurement2_done,
+            Measurement::Measurement3 => self.measurement3_done,
+            Measurement::Measurement4 => self.measurement4_done,
+        }
+    }
+
     pub(crate) fn to_u16(&self) -> u16 {
         let mut val = 0;
 
@@ -284,6 +469,9 @@ impl FDCConfiguration {
 pub (crate) struct FDC1004 {
     address: u8,
     output_rate: OutputRate,
+    timeout: Duration,
+    repeated_capdac: [Option<u8>; 4],
+    last_capdac: [u8; 4],
 }
 
 impl FDC1004 {
@@ -291,55 +479,219 @@ impl FDC1004 {
         FDC1004 {
             address,
             output_rate,
+            timeout: DEFAULT_TIMEOUT,
+            repeated_capdac: [None; 4],
+            last_capdac: [0; 4],
         }
     }
 
-    pub(crate) async fn read_capacitance<I2C, E>(&mut self, i2c: &mut I2C, channel: Channel) -> SuccessfulMeasurement where I2C: I2c<Error = E>, E: fmt::Debug {
-        let mut capdac: u8 = 0x00;
+    /// How long to wait for a triggered measurement before giving up with Timeout
+    /// Counted from the trigger, so it should be longer than the conversion time of the output rate
+    pub(crate) fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Soft-resets the device and checks it really is an FDC1004
+    /// Returns UnknownDevice with the IDs read when another part (or nothing) answers
+    pub(crate) async fn init<I2C, E>(&mut self, i2c: &mut I2C) -> Result<(), FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let mut config = FDCConfiguration::default();
+        config.rate(self.output_rate).reset(true);
+        self.write_u16(i2c, Register::FdcConf, config.to_u16()).await?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut backoff = POLL_BACKOFF_START;
+        while FDCConfiguration::from_u16(self.read_u16(i2c, Register::FdcConf).await?).reset {
+            if Instant::now() >= deadline {
+                return Err(FDCError::Timeout);
+            }
+
+            Timer::after(backoff).await;
+            backoff = (backoff * 2).min(self.conversion_time());
+        }
+
+        self.repeated_capdac = [None; 4];
+        self.last_capdac = [0; 4];
+
+        let manufacturer = self.read_u16(i2c, Register::ManufacturerId).await?;
+        let device = self.read_u16(i2c, Register::DeviceId).await?;
+        if manufacturer != MANUFACTURER_ID || device != DEVICE_ID {
+            return Err(FDCError::UnknownDevice { manufacturer, device });
+        }
+
+        Ok(())
+    }
+
+    /// Starts with the CAPDAC that last gave an in-range result on this channel and
+    /// binary searches the CAPDAC range while the measurement is saturated
+    pub(crate) async fn read_capacitance<I2C, E>(&mut self, i2c: &mut I2C, channel: Channel) -> Result<SuccessfulMeasurement, FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let index = channel.input_index().ok_or(FDCError::OutOfRange)? as usize;
+        let mut capdac = self.last_capdac[index];
+        let mut low: u8 = 0;
+        let mut high: u8 = CAPDAC_MAX;
 
         loop {
-            let m = self.measure_channel(i2c, channel, capdac).await.unwrap();
+            let m = self.measure_channel(i2c, channel, capdac).await?;
 //            log::info!("Measurment: {:?} Capdac: {:?}", m, capdac);
             if m < i24::max_value() && m > i24::min_value() {
-                return SuccessfulMeasurement::MeasurementInRange(MeasuredCapacitance::new(m, capdac));
+                self.last_capdac[index] = capdac;
+                return Ok(SuccessfulMeasurement::MeasurementInRange(MeasuredCapacitance::new(m, capdac)));
             }
 
-            if m == i24::max_value() && capdac < CAPDAC_MAX {
-                capdac += 1;
-            } else if m == i24::min_value() && capdac > 0 {
-                capdac -= 1;
+            if m == i24::max_value() {
+                low = capdac + 1;
             } else {
-                return match capdac {
-                    0 => SuccessfulMeasurement::Underflow,
-                    _ => SuccessfulMeasurement::Overflow
-                };
+                match capdac.checked_sub(1) {
+                    Some(c) => high = c,
+                    None => return Ok(SuccessfulMeasurement::Underflow),
+                }
             }
+
+            if low > high {
+                return Ok(match m == i24::max_value() {
+                    true => SuccessfulMeasurement::Overflow,
+                    false => SuccessfulMeasurement::Underflow,
+                });
+            }
+            capdac = low + (high - low) / 2;
         }
+    }
+
+    /// Measures CINa - CINb, e.g. against a reference electrode to cancel environmental drift
+    /// The CAPDAC can't be used in differential mode, so the result covers +-15 pF
+    pub(crate) async fn read_differential_capacitance<I2C, E>(&mut self, i2c: &mut I2C, channel_a: Channel, channel_b: Channel) -> Result<SuccessfulMeasurement, FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let m = self.measure_differential(i2c, channel_a, channel_b).await?;
+
+        Ok(if m == i24::max_value() {
+            SuccessfulMeasurement::Overflow
+        } else if m == i24::min_value() {
+            SuccessfulMeasurement::Underflow
+        } else {
+            SuccessfulMeasurement::MeasurementInRange(MeasuredCapacitance::new(m, 0))
+        })
+    }
+
+    pub(crate) async fn measure_differential<I2C, E>(&mut self, i2c: &mut I2C, channel_a: Channel, channel_b: Channel) -> Result<i24, FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let measurement = measurement_for(channel_a).ok_or(FDCError::OutOfRange)?;
 
+        self.configure_differential_measurement(i2c, channel_a, channel_b, measurement).await?;
+        self.trigger_single_measurement(i2c, measurement).await?;
+        self.wait_measurement_done(i2c, measurement).await?;
 
-        //m as f32 / 0x80_0000 as f32
-        return SuccessfulMeasurement::Overflow;
+        self.read_result(i2c, measurement).await
     }
 
     pub(crate) async fn measure_channel<I2C, E>(&mut self, i2c: &mut I2C, channel: Channel, capdac: u8) -> Result<i24, FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
-        let measurement = match channel {
-            Channel::CIN1 => Measurement::Measurement1,
-            Channel::CIN2 => Measurement::Measurement2,
-            Channel::CIN3 => Measurement::Measurement3,
-            Channel::CIN4 => Measurement::Measurement4,
-            _ => Measurement::Measurement1,
-        };
+        let measurement = measurement_for(channel).ok_or(FDCError::OutOfRange)?;
 
-        self.configure_single_measurement(i2c, channel, measurement.clone(), capdac).await;
-        self.trigger_single_measurement(i2c, measurement.clone()).await;
-        Timer::after_millis(self.sample_delay()).await;
+        self.configure_single_measurement(i2c, channel, measurement.clone(), capdac).await?;
+        self.trigger_single_measurement(i2c, measurement.clone()).await?;
+        self.wait_measurement_done(i2c, measurement).await?;
 
-        return self.read_measurement(i2c, measurement).await;
+        return self.read_result(i2c, measurement).await;
     }
 
-    pub(crate) async fn configure_single_measurement<I2C, E>(&mut self, i2c: &mut I2C, channel: Channel, measurement: Measurement, capdac: u8) where I2C: I2c<Error = E>, E: fmt::Debug {
-        let mut config = MeasurementConfiguration::new(channel, Channel::CAPDAC, capdac);
+    /// Configures one measurement slot per (channel, capdac) pair, in order, and lets the
+    /// device convert all of them continuously at the output rate
+    /// At most four slots, the results are collected with read_completed_measurements
+    pub(crate) async fn start_repeated_measurements<I2C, E>(&mut self, i2c: &mut I2C, channels: &[(Channel, u8)]) -> Result<(), FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        if channels.len() > MEASUREMENTS.len() {
+            return Err(FDCError::OutOfRange);
+        }
+
+        let mut config = FDCConfiguration::default();
+        config.rate(self.output_rate).repeat(true);
+        self.repeated_capdac = [None; 4];
+
+        for (&measurement, &(channel, capdac)) in MEASUREMENTS.iter().zip(channels) {
+            if channel.input_index().is_none() {
+                return Err(FDCError::OutOfRange);
+            }
+            self.configure_single_measurement(i2c, channel, measurement, capdac).await?;
+            config.initiate(measurement, true);
+        }
+
+        self.write_u16(i2c, Register::FdcConf, config.to_u16()).await?;
+        for (slot, &(_, capdac)) in self.repeated_capdac.iter_mut().zip(channels) {
+            *slot = Some(capdac);
+        }
+
+        Ok(())
+    }
+
+    /// Stops the repeated conversions, the slot configuration is kept
+    pub(crate) async fn stop_repeated_measurements<I2C, E>(&mut self, i2c: &mut I2C) -> Result<(), FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let mut config = FDCConfiguration::default();
+        config.rate(self.output_rate);
 
+        self.write_u16(i2c, Register::FdcConf, config.to_u16()).await?;
+        self.repeated_capdac = [None; 4];
+
+        Ok(())
+    }
+
+    /// Reads every slot whose done bit is set with a single FDC_CONF read
+    /// Slots that are not running or have no new result since the last read are None
+    pub(crate) async fn read_completed_measurements<I2C, E>(&mut self, i2c: &mut I2C) -> Result<[Option<MeasuredCapacitance>; 4], FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let config = FDCConfiguration::from_u16(self.read_u16(i2c, Register::FdcConf).await?);
+        let mut results = [None, None, None, None];
+
+        for (i, &measurement) in MEASUREMENTS.iter().enumerate() {
+            let Some(capdac) = self.repeated_capdac[i] else {
+                continue;
+            };
+            if config.done(measurement) {
+                let value = self.read_result(i2c, measurement).await?;
+                results[i] = Some(MeasuredCapacitance::new(value, capdac));
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Sleeps for the nominal conversion time, then polls the done bit with an exponential
+    /// backoff capped at a quarter of the conversion time until the timeout expires
+    pub(crate) async fn wait_measurement_done<I2C, E>(&mut self, i2c: &mut I2C, measurement: Measurement) -> Result<(), FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let deadline = Instant::now() + self.timeout;
+        let conversion_time = self.conversion_time();
+        let max_backoff = conversion_time / 4;
+        let mut backoff = POLL_BACKOFF_START;
+
+        Timer::after(conversion_time).await;
+        loop {
+            let config = FDCConfiguration::from_u16(self.read_u16(i2c, Register::FdcConf).await?);
+            if config.done(measurement) {
+                return Ok(());
+            }
+
+            if Instant::now() >= deadline {
+                return Err(FDCError::Timeout);
+            }
+
+            Timer::after(backoff).await;
+            backoff = (backoff * 2).min(max_backoff);
+        }
+    }
+
+    pub(crate) async fn configure_single_measurement<I2C, E>(&mut self, i2c: &mut I2C, channel: Channel, measurement: Measurement, capdac: u8) -> Result<(), FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        if capdac > CAPDAC_MAX {
+            return Err(FDCError::OutOfRange);
+        }
+
+        let config = MeasurementConfiguration::new(channel, Channel::CAPDAC, capdac);
+        self.write_measurement_configuration(i2c, measurement, config).await
+    }
+
+    /// Configures a slot to measure CINa - CINb
+    /// The datasheet only allows channel_b to be a higher input than channel_a
+    pub(crate) async fn configure_differential_measurement<I2C, E>(&mut self, i2c: &mut I2C, channel_a: Channel, channel_b: Channel, measurement: Measurement) -> Result<(), FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        validate_differential_pair(channel_a, channel_b)?;
+
+        let config = MeasurementConfiguration::new(channel_a, channel_b, 0);
+        self.write_measurement_configuration(i2c, measurement, config).await
+    }
+
+    async fn write_measurement_configuration<I2C, E>(&mut self, i2c: &mut I2C, measurement: Measurement, config: MeasurementConfiguration) -> Result<(), FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
         let reg = match measurement {
             Measurement::Measurement1 => Register::Measurement1Config,
             Measurement::Measurement2 => Register::Measurement2Config,
@@ -347,10 +699,10 @@ impl FDC1004 {
             Measurement::Measurement4 => Register::Measurement4Config,
         };
 
-        self.write_u16(i2c, reg, config.to_u16()).await;
+        self.write_u16(i2c, reg, config.to_u16()).await
     }
 
-    pub(crate) async fn trigger_single_measurement<I2C, E>(&mut self, i2c: &mut I2C, measurement: Measurement) where I2C: I2c<Error = E>, E: fmt::Debug {
+    pub(crate) async fn trigger_single_measurement<I2C, E>(&mut self, i2c: &mut I2C, measurement: Measurement) -> Result<(), FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
         let mut config = FDCConfiguration::default();
         let mut config = config.rate(self.output_rate);
         let mut config = match measurement {
@@ -360,36 +712,34 @@ impl FDC1004 {
             Measurement::Measurement4 => config.initiate_measurement4(true),
         };
 
-        self.write_u16(i2c, Register::FdcConf, config.to_u16()).await;
+        self.write_u16(i2c, Register::FdcConf, config.to_u16()).await
     }
 
     pub(crate) async fn read_measurement<I2C, E>(&mut self, i2c: &mut I2C, measurement: Measurement) -> Result<i24, FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
-        let config = FDCConfiguration::from_u16(self.read_u16(i2c, Register::FdcConf).await.unwrap());
-
-        let ready = match measurement {
-            Measurement::Measurement1 => config.measurement1_done,
-            Measurement::Measurement2 => config.measurement2_done,
-            Measurement::Measurement3 => config.measurement3_done,
-            Measurement::Measurement4 => config.measurement4_done,
-        };
+        let config = FDCConfiguration::from_u16(self.read_u16(i2c, Register::FdcConf).await?);
 
-        if (!ready) {
+        if !config.done(measurement) {
             return Err(FDCError::MeasurementNotComplete);
         }
 
+        self.read_result(i2c, measurement).await
+    }
+
+    /// Reads the result registers without checking the done bit
+    async fn read_result<I2C, E>(&mut self, i2c: &mut I2C, measurement: Measurement) -> Result<i24, FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
         let msb = self.read_u16(i2c, match measurement {
             Measurement::Measurement1 => Register::Measurement1MSB,
             Measurement::Measurement2 => Register::Measurement2MSB,
             Measurement::Measurement3 => Register::Measurement3MSB,
             Measurement::Measurement4 => Register::Measurement4MSB,
-        }).await.unwrap() as i32;
+        }).await? as i32;
 
         let lsb = self.read_u16(i2c, match measurement {
             Measurement::Measurement1 => Register::Measurement1LSB,
             Measurement::Measurement2 => Register::Measurement2LSB,
             Measurement::Measurement3 => Register::Measurement3LSB,
             Measurement::Measurement4 => Register::Measurement4LSB,
-        }).await.unwrap() as i32;
+        }).await? as i32;
 
         let mut val24 = i24::default();
         val24 |= i24::new(msb) << 8;
@@ -398,30 +748,449 @@ impl FDC1004 {
         Ok(val24)
     }
 
-    pub(crate) async fn write_u16<I2C, E>(&mut self, i2c: &mut I2C, reg: Register, data: u16) where I2C: I2c<Error = E>, E: fmt::Debug {
-        let data = data.to_be_bytes();
-        i2c.write(self.address, &[reg.to_u8(), data[0], data[1]]).await.unwrap();
+    /// Offset in pF the device adds to every measurement of the channel, +-16 pF
+    pub(crate) async fn read_offset_calibration<I2C, E>(&mut self, i2c: &mut I2C, channel: Channel) -> Result<I5F11, FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let reg = Register::offset_calibration(channel).ok_or(FDCError::OutOfRange)?;
+        Ok(I5F11::from_bits(self.read_u16(i2c, reg).await? as i16))
     }
 
-    pub(crate) async fn read_u16<I2C, E>(&mut self, i2c: &mut I2C, reg: Register) -> Result<u16, E> where I2C: I2c<Error = E>, E: fmt::Debug {
-        let mut data: [u8; 2] = [0,0];
-        let res = i2c.write_read(self.address, &[reg.to_u8()], &mut data).await;
+    pub(crate) async fn write_offset_calibration<I2C, E>(&mut self, i2c: &mut I2C, channel: Channel, offset: I5F11) -> Result<(), FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let reg = Register::offset_calibration(channel).ok_or(FDCError::OutOfRange)?;
+        self.write_u16(i2c, reg, offset.to_bits() as u16).await
+    }
+
+    /// Factor the device multiplies every measurement of the channel with, 0 to 4
+    pub(crate) async fn read_gain_calibration<I2C, E>(&mut self, i2c: &mut I2C, channel: Channel) -> Result<U2F14, FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let reg = Register::gain_calibration(channel).ok_or(FDCError::OutOfRange)?;
+        Ok(U2F14::from_bits(self.read_u16(i2c, reg).await?))
+    }
 
-        if res.is_err() {
-            return Err(res.err().unwrap());
+    pub(crate) async fn write_gain_calibration<I2C, E>(&mut self, i2c: &mut I2C, channel: Channel, gain: U2F14) -> Result<(), FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let reg = Register::gain_calibration(channel).ok_or(FDCError::OutOfRange)?;
+        self.write_u16(i2c, reg, gain.to_bits()).await
+    }
+
+    /// Measures the channels with an empty tank and programs offsets that bring them to 0 pF
+    /// Returns the offsets indexed by input (CIN1 first) so the caller can persist them and
+    /// restore them with write_offset_calibration after a power cycle
+    /// Fails with OutOfRange if the empty capacitance is beyond what the offset register can cancel
+    /// The CAPDAC remembered by read_capacitance is reset for every calibrated channel, with the
+    /// offset in place the empty channel reads around 0 pF and the search starts at CAPDAC 0 again
+    pub(crate) async fn calibrate_empty_offsets<I2C, E>(&mut self, i2c: &mut I2C, channels: &[Channel]) -> Result<[Option<I5F11>; 4], FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let mut offsets = [None; 4];
+
+        for &channel in channels {
+            let index = channel.input_index().ok_or(FDCError::OutOfRange)? as usize;
+            self.write_offset_calibration(i2c, channel, I5F11::ZERO).await?;
+            self.last_capdac[index] = 0;
+
+            let empty = match self.read_capacitance(i2c, channel).await? {
+                SuccessfulMeasurement::MeasurementInRange(cap) => cap.to_pf(),
+                _ => return Err(FDCError::OutOfRange),
+            };
+            let offset = I5F11::checked_from_num(-empty).ok_or(FDCError::OutOfRange)?;
+
+            self.write_offset_calibration(i2c, channel, offset).await?;
+            self.last_capdac[index] = 0;
+            offsets[index] = Some(offset);
         }
 
-        let be = u16::from_be_bytes(data);
+        Ok(offsets)
+    }
 
-        return Ok(be);
+    pub(crate) async fn write_u16<I2C, E>(&mut self, i2c: &mut I2C, reg: Register, data: u16) -> Result<(), FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let data = data.to_be_bytes();
+        i2c.write(self.address, &[reg.to_u8(), data[0], data[1]]).await.map_err(FDCError::I2CError)
     }
 
-    fn sample_delay(&self) -> u64 {
+    pub(crate) async fn read_u16<I2C, E>(&mut self, i2c: &mut I2C, reg: Register) -> Result<u16, FDCError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let mut data: [u8; 2] = [0,0];
+        i2c.write_read(self.address, &[reg.to_u8()], &mut data).await.map_err(FDCError::I2CError)?;
+
+        Ok(u16::from_be_bytes(data))
+    }
+
+    /// Nominal time of one measurement at the output rate
+    fn conversion_time(&self) -> Duration {
         match self.output_rate {
-            OutputRate::SPS100 => 11,
-            OutputRate::SPS200 => 6,
-            OutputRate::SPS400 => 3,
+            OutputRate::SPS100 => Duration::from_micros(10_000),
+            OutputRate::SPS200 => Duration::from_micros(5_000),
+            OutputRate::SPS400 => Duration::from_micros(2_500),
         }
     }
 }
 
+
+
+/// Single-ended measurements use the slot matching the input
+fn measurement_for(channel: Channel) -> Option<Measurement> {
+    channel.input_index().map(|i| MEASUREMENTS[i as usize])
+}
+
+pub(crate) fn validate_differential_pair<E>(channel_a: Channel, channel_b: Channel) -> Result<(), FDCError<E>> {
+    match (channel_a.input_index(), channel_b.input_index()) {
+        (Some(a), Some(b)) if b > a => Ok(()),
+        _ => Err(FDCError::InvalidChannelPair),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embedded_hal_1::i2c::ErrorKind;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    const ADDRESS: u8 = 0x50;
+
+    #[test]
+    fn measurement_configuration_bits() {
+        assert_eq!(MeasurementConfiguration::new(Channel::CIN1, Channel::CAPDAC, 0).to_u16(), 0x1000);
+        assert_eq!(MeasurementConfiguration::new(Channel::CIN4, Channel::CAPDAC, CAPDAC_MAX).to_u16(), 0x73E0);
+        assert_eq!(MeasurementConfiguration::new(Channel::CIN2, Channel::CIN3, 0).to_u16(), 0x2800);
+        assert_eq!(MeasurementConfiguration::new(Channel::CIN3, Channel::DISABLED, 0).to_u16(), 0x5C00);
+    }
+
+    #[test]
+    fn fdc_configuration_round_trip() {
+        for bits in [0x0400, 0x0C00, 0x8400, 0x0D80, 0x0488, 0x0D0F] {
+            assert_eq!(FDCConfiguration::from_u16(bits).to_u16(), bits);
+        }
+    }
+
+    #[test]
+    fn read_measurement_assembles_24_bits() {
+        let expectations = [
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x08]),
+            Transaction::write_read(ADDRESS, vec![0x00], vec![0x12, 0x34]),
+            Transaction::write_read(ADDRESS, vec![0x01], vec![0x56, 0x00]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x01]),
+            Transaction::write_read(ADDRESS, vec![0x06], vec![0xFF, 0xFF]),
+            Transaction::write_read(ADDRESS, vec![0x07], vec![0xFF, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        let value = block_on(fdc.read_measurement(&mut i2c, Measurement::Measurement1)).unwrap();
+        assert_eq!(value, i24::new(0x123456));
+
+        let value = block_on(fdc.read_measurement(&mut i2c, Measurement::Measurement4)).unwrap();
+        assert_eq!(value, i24::new(-1));
+        i2c.done();
+    }
+
+    #[test]
+    fn read_measurement_not_complete() {
+        let expectations = [
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x80]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        let result = block_on(fdc.read_measurement(&mut i2c, Measurement::Measurement1));
+        assert!(matches!(result, Err(FDCError::MeasurementNotComplete)));
+        i2c.done();
+    }
+
+    #[test]
+    fn read_capacitance_steps_capdac() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![0x08, 0x10, 0x00]),
+            Transaction::write(ADDRESS, vec![0x0C, 0x04, 0x80]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x88]),
+            Transaction::write_read(ADDRESS, vec![0x00], vec![0x7F, 0xFF]),
+            Transaction::write_read(ADDRESS, vec![0x01], vec![0xFF, 0x00]),
+            Transaction::write(ADDRESS, vec![0x08, 0x12, 0x00]),
+            Transaction::write(ADDRESS, vec![0x0C, 0x04, 0x80]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x88]),
+            Transaction::write_read(ADDRESS, vec![0x00], vec![0x00, 0x10]),
+            Transaction::write_read(ADDRESS, vec![0x01], vec![0x00, 0x00]),
+            Transaction::write(ADDRESS, vec![0x08, 0x12, 0x00]),
+            Transaction::write(ADDRESS, vec![0x0C, 0x04, 0x80]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x88]),
+            Transaction::write_read(ADDRESS, vec![0x00], vec![0x00, 0x20]),
+            Transaction::write_read(ADDRESS, vec![0x01], vec![0x00, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        match block_on(fdc.read_capacitance(&mut i2c, Channel::CIN1)).unwrap() {
+            SuccessfulMeasurement::MeasurementInRange(cap) => {
+                assert_eq!(cap.value, i24::new(0x1000));
+                assert_eq!(cap.capdac, 16);
+                assert!((cap.to_pf() - 50.0078125).abs() < 1e-4);
+            }
+            _ => panic!("measurement out of range"),
+        }
+
+        // the next reading starts at the remembered CAPDAC
+        match block_on(fdc.read_capacitance(&mut i2c, Channel::CIN1)).unwrap() {
+            SuccessfulMeasurement::MeasurementInRange(cap) => assert_eq!(cap.capdac, 16),
+            _ => panic!("measurement out of range"),
+        }
+        i2c.done();
+    }
+
+    #[test]
+    fn read_capacitance_overflow() {
+        let saturated = |capdac: u8| {
+            let config = 0x1000u16 | (u16::from(capdac) << 5);
+            [
+                Transaction::write(ADDRESS, vec![0x08, (config >> 8) as u8, config as u8]),
+                Transaction::write(ADDRESS, vec![0x0C, 0x04, 0x80]),
+                Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x88]),
+                Transaction::write_read(ADDRESS, vec![0x00], vec![0x7F, 0xFF]),
+                Transaction::write_read(ADDRESS, vec![0x01], vec![0xFF, 0x00]),
+            ]
+        };
+        let expectations: Vec<Transaction> = [0, 16, 24, 28, 30, 31].into_iter().flat_map(saturated).collect();
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        let result = block_on(fdc.read_capacitance(&mut i2c, Channel::CIN1));
+        assert!(matches!(result, Ok(SuccessfulMeasurement::Overflow)));
+        i2c.done();
+    }
+
+    #[test]
+    fn read_capacitance_underflow() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![0x0B, 0x70, 0x00]),
+            Transaction::write(ADDRESS, vec![0x0C, 0x04, 0x10]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x11]),
+            Transaction::write_read(ADDRESS, vec![0x06], vec![0x80, 0x00]),
+            Transaction::write_read(ADDRESS, vec![0x07], vec![0x00, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        let result = block_on(fdc.read_capacitance(&mut i2c, Channel::CIN4));
+        assert!(matches!(result, Ok(SuccessfulMeasurement::Underflow)));
+        i2c.done();
+    }
+
+    #[test]
+    fn measure_channel_polls_until_done() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![0x09, 0x30, 0x00]),
+            Transaction::write(ADDRESS, vec![0x0C, 0x04, 0x40]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x40]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x40]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x44]),
+            Transaction::write_read(ADDRESS, vec![0x02], vec![0x00, 0x20]),
+            Transaction::write_read(ADDRESS, vec![0x03], vec![0x00, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        let value = block_on(fdc.measure_channel(&mut i2c, Channel::CIN2, 0)).unwrap();
+        assert_eq!(value, i24::new(0x2000));
+        i2c.done();
+    }
+
+    #[test]
+    fn measure_channel_timeout() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![0x08, 0x10, 0x00]),
+            Transaction::write(ADDRESS, vec![0x0C, 0x04, 0x80]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x80]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100).timeout(Duration::from_ticks(0));
+
+        let result = block_on(fdc.measure_channel(&mut i2c, Channel::CIN1, 0));
+        assert!(matches!(result, Err(FDCError::Timeout)));
+        i2c.done();
+    }
+
+    #[test]
+    fn repeated_measurements() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![0x08, 0x10, 0x00]),
+            Transaction::write(ADDRESS, vec![0x09, 0x30, 0x40]),
+            Transaction::write(ADDRESS, vec![0x0C, 0x09, 0xC0]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x09, 0xC4]),
+            Transaction::write_read(ADDRESS, vec![0x02], vec![0x00, 0x10]),
+            Transaction::write_read(ADDRESS, vec![0x03], vec![0x00, 0x00]),
+            Transaction::write(ADDRESS, vec![0x0C, 0x08, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS200);
+
+        block_on(fdc.start_repeated_measurements(&mut i2c, &[(Channel::CIN1, 0), (Channel::CIN2, 2)])).unwrap();
+        let results = block_on(fdc.read_completed_measurements(&mut i2c)).unwrap();
+        assert!(results[0].is_none());
+        let cin2 = results[1].as_ref().unwrap();
+        assert_eq!(cin2.value, i24::new(0x1000));
+        assert_eq!(cin2.capdac, 2);
+        assert!(results[2].is_none() && results[3].is_none());
+        block_on(fdc.stop_repeated_measurements(&mut i2c)).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn repeated_measurements_rejects_too_many_slots() {
+        let mut i2c = Mock::new(&[]);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        let channels = [(Channel::CIN1, 0); 5];
+        let result = block_on(fdc.start_repeated_measurements(&mut i2c, &channels));
+        assert!(matches!(result, Err(FDCError::OutOfRange)));
+        i2c.done();
+    }
+
+    #[test]
+    fn differential_pair_validation() {
+        assert!(validate_differential_pair::<()>(Channel::CIN1, Channel::CIN2).is_ok());
+        assert!(validate_differential_pair::<()>(Channel::CIN3, Channel::CIN4).is_ok());
+        assert!(matches!(validate_differential_pair::<()>(Channel::CIN2, Channel::CIN2), Err(FDCError::InvalidChannelPair)));
+        assert!(matches!(validate_differential_pair::<()>(Channel::CIN4, Channel::CIN1), Err(FDCError::InvalidChannelPair)));
+        assert!(matches!(validate_differential_pair::<()>(Channel::CIN1, Channel::CAPDAC), Err(FDCError::InvalidChannelPair)));
+    }
+
+    #[test]
+    fn read_differential_capacitance() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![0x09, 0x2C, 0x00]),
+            Transaction::write(ADDRESS, vec![0x0C, 0x04, 0x40]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x44]),
+            Transaction::write_read(ADDRESS, vec![0x02], vec![0xF0, 0x00]),
+            Transaction::write_read(ADDRESS, vec![0x03], vec![0x00, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        match block_on(fdc.read_differential_capacitance(&mut i2c, Channel::CIN2, Channel::CIN4)).unwrap() {
+            SuccessfulMeasurement::MeasurementInRange(cap) => {
+                assert_eq!(cap.value, i24::new(-0x100000));
+                assert!((cap.to_pf() + 2.0).abs() < 1e-6);
+            }
+            _ => panic!("measurement out of range"),
+        }
+        i2c.done();
+    }
+
+    #[test]
+    fn calibration_register_encoding() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![0x0E, 0xF4, 0x00]),
+            Transaction::write_read(ADDRESS, vec![0x0E], vec![0xF4, 0x00]),
+            Transaction::write(ADDRESS, vec![0x14, 0x60, 0x00]),
+            Transaction::write_read(ADDRESS, vec![0x14], vec![0x60, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        block_on(fdc.write_offset_calibration(&mut i2c, Channel::CIN2, I5F11::from_num(-1.5))).unwrap();
+        assert_eq!(block_on(fdc.read_offset_calibration(&mut i2c, Channel::CIN2)).unwrap(), I5F11::from_num(-1.5));
+        // 1.5 is 0x6000 with 14 fractional bits
+        block_on(fdc.write_gain_calibration(&mut i2c, Channel::CIN4, U2F14::from_num(1.5))).unwrap();
+        assert_eq!(block_on(fdc.read_gain_calibration(&mut i2c, Channel::CIN4)).unwrap(), U2F14::from_num(1.5));
+        i2c.done();
+    }
+
+    #[test]
+    fn calibrate_empty_offsets() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![0x0D, 0x00, 0x00]),
+            Transaction::write(ADDRESS, vec![0x08, 0x10, 0x00]),
+            Transaction::write(ADDRESS, vec![0x0C, 0x04, 0x80]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x88]),
+            Transaction::write_read(ADDRESS, vec![0x00], vec![0x14, 0x00]),
+            Transaction::write_read(ADDRESS, vec![0x01], vec![0x00, 0x00]),
+            Transaction::write(ADDRESS, vec![0x0D, 0xEC, 0x00]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+        // CAPDAC remembered from before the calibration, the empty measurement starts at 0 regardless
+        fdc.last_capdac = [5, 5, 0, 0];
+
+        let offsets = block_on(fdc.calibrate_empty_offsets(&mut i2c, &[Channel::CIN1])).unwrap();
+        assert_eq!(offsets, [Some(I5F11::from_num(-2.5)), None, None, None]);
+        assert_eq!(fdc.last_capdac, [0, 5, 0, 0]);
+        i2c.done();
+    }
+
+    #[test]
+    fn init_resets_and_checks_ids() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![0x0C, 0x84, 0x00]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x84, 0x00]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x00]),
+            Transaction::write_read(ADDRESS, vec![0xFE], vec![0x54, 0x49]),
+            Transaction::write_read(ADDRESS, vec![0xFF], vec![0x10, 0x04]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        block_on(fdc.init(&mut i2c)).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn init_rejects_unknown_device() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![0x0C, 0x84, 0x00]),
+            Transaction::write_read(ADDRESS, vec![0x0C], vec![0x04, 0x00]),
+            Transaction::write_read(ADDRESS, vec![0xFE], vec![0x54, 0x49]),
+            Transaction::write_read(ADDRESS, vec![0xFF], vec![0x10, 0x05]),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        let result = block_on(fdc.init(&mut i2c));
+        assert!(matches!(result, Err(FDCError::UnknownDevice { manufacturer: 0x5449, device: 0x1005 })));
+        i2c.done();
+    }
+
+    #[test]
+    fn capacitance_from_measurement() {
+        assert_eq!(Capacitance::from_measurement(i24::new(0x1000), 16).attofarads(), 50_007_813);
+        assert_eq!(Capacitance::from_measurement(i24::new(0x80000), 0), Capacitance::from_picofarads(1));
+        assert_eq!(Capacitance::from_measurement(i24::new(-0x100000), 0), Capacitance::from_picofarads(-2));
+        assert_eq!(Capacitance::from_measurement(i24::new(0), CAPDAC_MAX).femtofarads(), 96_875);
+    }
+
+    #[test]
+    fn capacitance_arithmetic_and_format() {
+        let a = Capacitance::from_femtofarads(12_345);
+        let b = Capacitance::from_picofarads(15);
+
+        assert_eq!(a - b, Capacitance::from_attofarads(-2_655_000));
+        assert_eq!(-(a - b), (b - a).abs());
+        assert!(a < b);
+        assert_eq!(format!("{}", a), "12.345000 pF");
+        assert_eq!(format!("{:?}", a - b), "-2.655000 pF");
+        assert!(((a + b).to_pf() - 27.345).abs() < 1e-5);
+        assert!(((a - b).to_pf() + 2.655).abs() < 1e-5);
+
+        let min = Capacitance::from_attofarads(i64::MIN);
+        assert_eq!(format!("{}", min), "-9223372036854.775808 pF");
+        assert!(min.to_pf() < -9.2e12);
+    }
+
+    #[test]
+    fn read_capacitance_bus_error() {
+        let expectations = [
+            Transaction::write(ADDRESS, vec![0x08, 0x10, 0x00]).with_error(ErrorKind::Other),
+        ];
+        let mut i2c = Mock::new(&expectations);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        let result = block_on(fdc.read_capacitance(&mut i2c, Channel::CIN1));
+        assert!(matches!(result, Err(FDCError::I2CError(ErrorKind::Other))));
+        i2c.done();
+    }
+
+    #[test]
+    fn measure_channel_rejects_invalid_arguments() {
+        let mut i2c = Mock::new(&[]);
+        let mut fdc = FDC1004::new(ADDRESS, OutputRate::SPS100);
+
+        let result = block_on(fdc.measure_channel(&mut i2c, Channel::CAPDAC, 0));
+        assert!(matches!(result, Err(FDCError::OutOfRange)));
+        let result = block_on(fdc.measure_channel(&mut i2c, Channel::CIN1, CAPDAC_MAX + 1));
+        assert!(matches!(result, Err(FDCError::OutOfRange)));
+        i2c.done();
+    }
+}
@@ -0,0 +1,421 @@
+use core::cell::RefCell;
+use core::fmt;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel as CommandChannel;
+use embassy_time::Instant;
+use embedded_hal_async::i2c::I2c;
+use crate::iox::analog_input::fdc1004::{Channel, FDCError, FDC1004, SuccessfulMeasurement};
+
+/// Smallest reference - environmental difference in pF that is accepted as a liquid signal
+pub const DEFAULT_MIN_SPAN_PF: f32 = 0.25;
+/// Smallest full tank ratio accepted, the level electrode has to move at least a tenth
+/// as much as the reference between empty and full for the percentage to be meaningful
+pub const MIN_FULL_RATIO: f32 = 0.1;
+
+#[derive(Debug)]
+pub enum LevelError<E>{
+    NotCalibrated,
+    /// An electrode is saturated even with the CAPDAC at its limit
+    OutOfRange,
+    /// The reference and environmental electrodes differ by less than the minimum span,
+    /// or the full calibration point barely differs from the empty one
+    InvalidReference,
+    Sensor(FDCError<E>),
+}
+
+/// Inputs the probe electrodes are wired to
+/// level runs along the whole height of the tank, reference sits at the bottom and is
+/// always submerged, environmental sits at the top and is never submerged
+#[derive(Debug, Clone, Copy)]
+pub struct LevelElectrodes{
+    pub level: Channel,
+    pub reference: Channel,
+    pub environmental: Channel,
+}
+
+/// One capacitance reading of every electrode in pF
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ElectrodeReadings{
+    pub level: f32,
+    pub reference: f32,
+    pub environmental: f32,
+}
+
+impl ElectrodeReadings {
+    /// Level electrode change relative to the empty tank, normalised by the
+    /// reference - environmental difference so it doesn't depend on the liquid's permittivity
+    /// None if that difference is smaller than min_span pF
+    pub fn ratio(&self, level_empty: f32, min_span: f32) -> Option<f32> {
+        let liquid = self.reference - self.environmental;
+        if liquid.abs() < min_span {
+            return None;
+        }
+
+        Some((self.level - level_empty) / liquid)
+    }
+}
+
+/// Empty and full calibration points of a probe, persisted by the caller
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelCalibration{
+    /// Level electrode capacitance with the tank empty in pF
+    pub level_empty: f32,
+    /// Ratio with the tank full
+    pub full_ratio: f32,
+}
+
+impl LevelCalibration {
+    /// None if the reference span is below min_span pF or the calibration itself is implausible
+    pub fn percent(&self, readings: &ElectrodeReadings, min_span: f32) -> Option<f32> {
+        if self.full_ratio < MIN_FULL_RATIO {
+            return None;
+        }
+
+        readings.ratio(self.level_empty, min_span).map(|r| r / self.full_ratio * 100f32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum LevelEvent{
+    LowWater,
+    Refilled,
+}
+
+/// Low-water alarm with hysteresis
+/// Raises LowWater when the level drops to low and Refilled only once it climbs back to refill
+#[derive(Debug, Clone, Copy)]
+pub struct LevelAlarm{
+    low: f32,
+    refill: f32,
+    low_water: bool,
+}
+
+impl LevelAlarm {
+    pub fn new(low: f32, refill: f32) -> Self {
+        LevelAlarm {
+            low,
+            refill: refill.max(low),
+            low_water: false,
+        }
+    }
+
+    pub fn is_low_water(&self) -> bool {
+        self.low_water
+    }
+
+    pub fn update(&mut self, percent: f32) -> Option<LevelEvent> {
+        if !self.low_water && percent <= self.low {
+            self.low_water = true;
+            return Some(LevelEvent::LowWater);
+        }
+
+        if self.low_water && percent >= self.refill {
+            self.low_water = false;
+            return Some(LevelEvent::Refilled);
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LevelState{
+    pub percent: f32,
+    pub millimetres: f32,
+    pub low_water: bool,
+    pub timestamp: Instant,
+}
+
+/// Requests for the task owning the bus, handled by LevelSensor::handle_commands
+#[derive(Debug, Clone, Copy)]
+pub enum LevelCommand{
+    /// Restores calibration points the caller persisted earlier
+    LoadCalibration(LevelCalibration),
+    /// Captures the empty point, send with the tank drained
+    CalibrateEmpty,
+    /// Captures the full point, send with the tank filled to the probe height
+    CalibrateFull,
+}
+
+/// Latest level and calibration, shared between the task owning the bus and the rest of the firmware
+/// Calibration commands travel the other way
+pub(crate) struct PublishedLevel {
+    state: Mutex<CriticalSectionRawMutex, RefCell<Option<LevelState>>>,
+    calibration: Mutex<CriticalSectionRawMutex, RefCell<Option<LevelCalibration>>>,
+    commands: CommandChannel<CriticalSectionRawMutex, LevelCommand, 2>,
+}
+
+impl PublishedLevel {
+    pub(crate) const fn new() -> Self {
+        PublishedLevel {
+            state: Mutex::new(RefCell::new(None)),
+            calibration: Mutex::new(RefCell::new(None)),
+            commands: CommandChannel::new(),
+        }
+    }
+
+    pub(crate) fn latest(&self) -> Option<LevelState> {
+        self.state.lock(|s| *s.borrow())
+    }
+
+    /// Calibration in use, persist it after CalibrateFull and load it with LoadCalibration on the next boot
+    pub(crate) fn calibration(&self) -> Option<LevelCalibration> {
+        self.calibration.lock(|c| *c.borrow())
+    }
+
+    /// Queues a command, false if too many are still pending
+    pub(crate) fn request(&self, command: LevelCommand) -> bool {
+        self.commands.try_send(command).is_ok()
+    }
+
+    fn publish(&self, state: LevelState) {
+        self.state.lock(|s| *s.borrow_mut() = Some(state));
+    }
+
+    fn publish_calibration(&self, calibration: LevelCalibration) {
+        self.calibration.lock(|c| *c.borrow_mut() = Some(calibration));
+    }
+}
+
+/// Ratiometric water level measurement with a three electrode probe
+pub(crate) struct LevelSensor {
+    electrodes: LevelElectrodes,
+    height_mm: f32,
+    min_span: f32,
+    calibration: Option<LevelCalibration>,
+    alarm: Option<LevelAlarm>,
+    empty: Option<f32>,
+}
+
+impl LevelSensor {
+    /// height_mm is the distance between the empty and full calibration points
+    pub(crate) fn new(electrodes: LevelElectrodes, height_mm: f32) -> Self {
+        LevelSensor {
+            electrodes,
+            height_mm,
+            min_span: DEFAULT_MIN_SPAN_PF,
+            calibration: None,
+            alarm: None,
+            empty: None,
+        }
+    }
+
+    /// Restores previously captured calibration points
+    pub(crate) fn calibration(mut self, calibration: LevelCalibration) -> Self {
+        self.calibration = Some(calibration);
+        self
+    }
+
+    /// Smallest reference - environmental difference in pF that is trusted, readings below
+    /// it are rejected with InvalidReference instead of producing a meaningless level
+    pub(crate) fn min_span(mut self, min_span: f32) -> Self {
+        self.min_span = min_span;
+        self
+    }
+
+    /// Low-water alarm thresholds in percent
+    pub(crate) fn alarm(mut self, low: f32, refill: f32) -> Self {
+        self.alarm = Some(LevelAlarm::new(low, refill));
+        self
+    }
+
+    pub(crate) fn get_calibration(&self) -> Option<LevelCalibration> {
+        self.calibration
+    }
+
+    pub(crate) async fn read_electrodes<I2C, E>(&self, fdc: &mut FDC1004, i2c: &mut I2C) -> Result<ElectrodeReadings, LevelError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        Ok(ElectrodeReadings {
+            level: read_pf(fdc, i2c, self.electrodes.level).await?,
+            reference: read_pf(fdc, i2c, self.electrodes.reference).await?,
+            environmental: read_pf(fdc, i2c, self.electrodes.environmental).await?,
+        })
+    }
+
+    /// Captures the empty point, call with the tank drained
+    pub(crate) async fn calibrate_empty<I2C, E>(&mut self, fdc: &mut FDC1004, i2c: &mut I2C) -> Result<(), LevelError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let readings = self.read_electrodes(fdc, i2c).await?;
+        self.empty = Some(readings.level);
+        Ok(())
+    }
+
+    /// Captures the full point after calibrate_empty, call with the tank filled to height_mm
+    pub(crate) async fn calibrate_full<I2C, E>(&mut self, fdc: &mut FDC1004, i2c: &mut I2C) -> Result<LevelCalibration, LevelError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let level_empty = self.empty.ok_or(LevelError::NotCalibrated)?;
+        let readings = self.read_electrodes(fdc, i2c).await?;
+        let full_ratio = readings.ratio(level_empty, self.min_span).ok_or(LevelError::InvalidReference)?;
+        if full_ratio < MIN_FULL_RATIO {
+            return Err(LevelError::InvalidReference);
+        }
+
+        let calibration = LevelCalibration { level_empty, full_ratio };
+        self.calibration = Some(calibration);
+        Ok(calibration)
+    }
+
+    /// Runs the commands queued on published, the resulting calibration is published for persisting
+    /// Stops at the first command that fails
+    pub(crate) async fn handle_commands<I2C, E>(&mut self, fdc: &mut FDC1004, i2c: &mut I2C, published: &PublishedLevel) -> Result<(), LevelError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        while let Ok(command) = published.commands.try_receive() {
+            match command {
+                LevelCommand::LoadCalibration(calibration) => {
+                    if calibration.full_ratio < MIN_FULL_RATIO {
+                        return Err(LevelError::InvalidReference);
+                    }
+                    self.calibration = Some(calibration);
+                }
+                LevelCommand::CalibrateEmpty => {
+                    self.calibrate_empty(fdc, i2c).await?;
+                    continue;
+                }
+                LevelCommand::CalibrateFull => {
+                    self.calibrate_full(fdc, i2c).await?;
+                }
+            }
+
+            if let Some(calibration) = self.calibration {
+                published.publish_calibration(calibration);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Measures the level, runs the alarm and publishes the new state
+    pub(crate) async fn update<I2C, E>(&mut self, fdc: &mut FDC1004, i2c: &mut I2C, published: &PublishedLevel) -> Result<(LevelState, Option<LevelEvent>), LevelError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+        let calibration = self.calibration.ok_or(LevelError::NotCalibrated)?;
+        let readings = self.read_electrodes(fdc, i2c).await?;
+
+        let (state, event) = self.evaluate(&calibration, &readings)?;
+        published.publish(state);
+
+        Ok((state, event))
+    }
+
+    fn evaluate<E>(&mut self, calibration: &LevelCalibration, readings: &ElectrodeReadings) -> Result<(LevelState, Option<LevelEvent>), LevelError<E>> {
+        let percent = calibration.percent(readings, self.min_span).ok_or(LevelError::InvalidReference)?;
+        let event = self.alarm.as_mut().and_then(|a| a.update(percent));
+
+        let state = LevelState {
+            percent,
+            millimetres: percent / 100f32 * self.height_mm,
+            low_water: self.alarm.is_some_and(|a| a.is_low_water()),
+            timestamp: Instant::now(),
+        };
+
+        Ok((state, event))
+    }
+}
+
+async fn read_pf<I2C, E>(fdc: &mut FDC1004, i2c: &mut I2C, channel: Channel) -> Result<f32, LevelError<E>> where I2C: I2c<Error = E>, E: fmt::Debug {
+    match fdc.read_capacitance(i2c, channel).await.map_err(LevelError::Sensor)? {
+        SuccessfulMeasurement::MeasurementInRange(cap) => Ok(cap.to_pf()),
+        _ => Err(LevelError::OutOfRange),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ELECTRODES: LevelElectrodes = LevelElectrodes {
+        level: Channel::CIN1,
+        reference: Channel::CIN2,
+        environmental: Channel::CIN3,
+    };
+
+    fn readings(level: f32, reference: f32) -> ElectrodeReadings {
+        ElectrodeReadings { level, reference, environmental: 10f32 }
+    }
+
+    #[test]
+    fn ratio_compensates_permittivity() {
+        let calibration = LevelCalibration { level_empty: 20f32, full_ratio: readings(40f32, 14f32).ratio(20f32, DEFAULT_MIN_SPAN_PF).unwrap() };
+
+        assert!((calibration.percent(&readings(30f32, 14f32), DEFAULT_MIN_SPAN_PF).unwrap() - 50f32).abs() < 1e-4);
+        // a liquid with half the permittivity moves both the level and the reference half as much
+        assert!((calibration.percent(&readings(25f32, 12f32), DEFAULT_MIN_SPAN_PF).unwrap() - 50f32).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rejects_noise_level_reference() {
+        assert!(readings(25f32, 10f32).ratio(20f32, DEFAULT_MIN_SPAN_PF).is_none());
+        assert!(readings(25f32, 10.01f32).ratio(20f32, DEFAULT_MIN_SPAN_PF).is_none());
+        assert!(readings(25f32, 10.01f32).ratio(20f32, 0.005f32).is_some());
+
+        let implausible = LevelCalibration { level_empty: 20f32, full_ratio: 0.01f32 };
+        assert!(implausible.percent(&readings(25f32, 14f32), DEFAULT_MIN_SPAN_PF).is_none());
+
+        let calibration = LevelCalibration { level_empty: 20f32, full_ratio: 5f32 };
+        let mut sensor = LevelSensor::new(ELECTRODES, 400f32).calibration(calibration);
+        let result = sensor.evaluate::<()>(&calibration, &readings(22.5f32, 10.1f32));
+        assert!(matches!(result, Err(LevelError::InvalidReference)));
+    }
+
+    #[test]
+    fn load_calibration_command() {
+        use embedded_hal_mock::eh1::i2c::Mock;
+        use embassy_futures::block_on;
+        use crate::iox::analog_input::fdc1004::OutputRate;
+
+        let mut i2c = Mock::new(&[]);
+        let mut fdc = FDC1004::new(0x50, OutputRate::SPS100);
+        let published = PublishedLevel::new();
+        let mut sensor = LevelSensor::new(ELECTRODES, 400f32);
+
+        let implausible = LevelCalibration { level_empty: 20f32, full_ratio: 0.01f32 };
+        assert!(published.request(LevelCommand::LoadCalibration(implausible)));
+        let result = block_on(sensor.handle_commands(&mut fdc, &mut i2c, &published));
+        assert!(matches!(result, Err(LevelError::InvalidReference)));
+        assert_eq!(sensor.get_calibration(), None);
+
+        let calibration = LevelCalibration { level_empty: 20f32, full_ratio: 5f32 };
+        assert!(published.request(LevelCommand::LoadCalibration(calibration)));
+        block_on(sensor.handle_commands(&mut fdc, &mut i2c, &published)).unwrap();
+        assert_eq!(sensor.get_calibration(), Some(calibration));
+        assert_eq!(published.calibration(), Some(calibration));
+
+        // calibrate_full without an empty point fails before touching the bus
+        assert!(published.request(LevelCommand::CalibrateFull));
+        let result = block_on(sensor.handle_commands(&mut fdc, &mut i2c, &published));
+        assert!(matches!(result, Err(LevelError::NotCalibrated)));
+        i2c.done();
+    }
+
+    #[test]
+    fn command_queue_is_bounded() {
+        let published = PublishedLevel::new();
+        assert!(published.request(LevelCommand::CalibrateEmpty));
+        assert!(published.request(LevelCommand::CalibrateFull));
+        assert!(!published.request(LevelCommand::CalibrateFull));
+    }
+
+    #[test]
+    fn alarm_hysteresis() {
+        let mut alarm = LevelAlarm::new(20f32, 30f32);
+
+        assert_eq!(alarm.update(50f32), None);
+        assert_eq!(alarm.update(20f32), Some(LevelEvent::LowWater));
+        assert_eq!(alarm.update(15f32), None);
+        assert_eq!(alarm.update(25f32), None);
+        assert!(alarm.is_low_water());
+        assert_eq!(alarm.update(30f32), Some(LevelEvent::Refilled));
+        assert_eq!(alarm.update(25f32), None);
+    }
+
+    #[test]
+    fn evaluate_scales_to_height() {
+        let calibration = LevelCalibration { level_empty: 20f32, full_ratio: 5f32 };
+        let mut sensor = LevelSensor::new(ELECTRODES, 400f32).calibration(calibration).alarm(10f32, 20f32);
+
+        let (state, event) = sensor.evaluate::<()>(&calibration, &readings(22.5f32, 12f32)).unwrap();
+        assert!((state.percent - 25f32).abs() < 1e-4);
+        assert!((state.millimetres - 100f32).abs() < 1e-3);
+        assert!(!state.low_water);
+        assert_eq!(event, None);
+
+        let (state, event) = sensor.evaluate::<()>(&calibration, &readings(20.5f32, 12f32)).unwrap();
+        assert!(state.low_water);
+        assert_eq!(event, Some(LevelEvent::LowWater));
+    }
+}
@@ -1,16 +1,28 @@
 use embassy_rp::gpio;
 use embassy_rp::gpio::AnyPin;
 use gpio::Output;
-use crate::iox::binary_output::dual_c595_shift_register::DualC595ShiftRegister;
 use crate::iox::Flushable;
-use core::cell::{Cell, RefCell};
+use crate::iox::shift_register_frame::ShiftRegisterOutputs;
+use embassy_time::Timer;
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 
-pub(crate) mod dual_c595_shift_register;
+pub(crate) mod c595_chain;
+pub(crate) mod pio_c595_chain;
 
 pub(crate) trait BinaryOutput {
     fn set_deferred(&mut self, val: bool);
 }
 
+/// Clocks a frame into a chain of N shift registers and latches it to the outputs
+pub(crate) trait ShiftRegisterBackend<const N: usize> {
+    async fn write(&mut self, frame: &[u8; N]);
+
+    /// Pulses the storage clock only, copying the shift stages to the outputs
+    async fn latch(&mut self);
+}
+
 pub(crate) struct SioOutput<'a> {
     deferred_val: bool,
     pin: Output<'a, AnyPin>,
@@ -40,17 +52,129 @@ impl BinaryOutput for SioOutput<'_> {
     }
 }
 
-pub(crate) struct ShiftRegister<'a> {
-    out: ShiftRegisterOutputs,
-    reg: DualC595ShiftRegister<'a>
+struct OutputEnableState<'a> {
+    ng_pin: Option<Output<'a, AnyPin>>,
+    latched: bool,
+    disabled: bool,
 }
 
-impl<'a> ShiftRegister<'a> {
-    pub(crate) fn new(reg: DualC595ShiftRegister<'a>) -> Self {
+/// Active low output enable (G) of a shift register chain
+/// Lives apart from the ShiftRegister so a fault path in another task can switch the outputs off
+/// while the task owning the chain is busy or blocked
+pub(crate) struct OutputEnable<'a> {
+    state: Mutex<CriticalSectionRawMutex, RefCell<OutputEnableState<'a>>>,
+}
+
+impl<'a> OutputEnable<'a> {
+    pub(crate) const fn new() -> Self {
+        OutputEnable {
+            state: Mutex::new(RefCell::new(OutputEnableState {
+                ng_pin: None,
+                latched: false,
+                disabled: false,
+            })),
+        }
+    }
+
+    /// Takes over the G pin, the outputs are disabled until the first frame is latched
+    pub(crate) fn set_pin(&self, mut ng_pin: Output<'a, AnyPin>) {
+        ng_pin.set_high();
+        self.state.lock(|s| s.borrow_mut().ng_pin = Some(ng_pin));
+    }
+
+    /// Emergency off, releases G so every output goes high impedance without touching the bus
+    /// The outputs stay off until enable_outputs is called
+    pub(crate) fn disable_all(&self) {
+        self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.disabled = true;
+            s.update();
+        });
+    }
+
+    /// Re-enables the outputs after disable_all, once a frame has been latched
+    pub(crate) fn enable_outputs(&self) {
+        self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.disabled = false;
+            s.update();
+        });
+    }
+
+    pub(crate) fn is_disabled(&self) -> bool {
+        self.state.lock(|s| s.borrow().disabled)
+    }
+
+    /// Called by the chain once valid data sits in the storage registers
+    fn latched(&self) {
+        self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.latched = true;
+            s.update();
+        });
+    }
+}
+
+impl OutputEnableState<'_> {
+    fn update(&mut self) {
+        let enabled = self.latched && !self.disabled;
+        if let Some(ng) = self.ng_pin.as_mut() {
+            match enabled {
+                true => ng.set_low(),
+                false => ng.set_high(),
+            }
+        }
+    }
+}
+
+/// Deferred outputs of a shift register chain, reg is either the bit-banged
+/// C595Chain or the PioC595Chain
+/// When an OutputEnable is handed over the outputs stay off until the first frame is latched
+pub(crate) struct ShiftRegister<'a, B: ShiftRegisterBackend<N>, const N: usize> {
+    out: ShiftRegisterOutputs<N>,
+    reg: B,
+    output_enable: Option<&'a OutputEnable<'a>>,
+    srclr_pin: Option<Output<'a, AnyPin>>,
+}
+
+impl<'a, B: ShiftRegisterBackend<N>, const N: usize> ShiftRegister<'a, B, N> {
+    pub(crate) fn new(reg: B) -> Self {
         ShiftRegister {
             out: ShiftRegisterOutputs::new(),
-            reg
+            reg,
+            output_enable: None,
+            srclr_pin: None,
+        }
+    }
+
+    /// Drives the shared output enable, which only releases the outputs once a frame is latched
+    pub(crate) fn output_enable(mut self, output_enable: &'a OutputEnable<'a>) -> Self {
+        self.output_enable = Some(output_enable);
+        self
+    }
+
+    /// Takes over the active low shift register clear
+    pub(crate) fn clear_pin(mut self, mut srclr_pin: Output<'a, AnyPin>) -> Self {
+        srclr_pin.set_high();
+        self.srclr_pin = Some(srclr_pin);
+        self
+    }
+
+    /// Clears the chain through SRCLR and latches the cleared stages to the outputs
+    /// Falls back to writing an all-off frame if the pin is not available
+    pub(crate) async fn hardware_clear(&mut self) {
+        self.clear();
+        match self.srclr_pin.as_mut() {
+            Some(srclr) => {
+                srclr.set_low();
+                Timer::after_micros(1).await;
+                srclr.set_high();
+                self.reg.latch().await;
+            }
+            None => self.reg.write(self.out.get_value()).await,
         }
+
+        self.update_output_enable();
     }
     
     pub(crate) fn clear(&mut self) {
@@ -68,7 +192,7 @@ impl<'a> ShiftRegister<'a> {
     }
     
     pub(crate) fn set_all_outputs(&mut self, value: bool) {
-        for i in 0..16 {
+        for i in 0..ShiftRegisterOutputs::<N>::OUTPUTS {
             self.set_output(i, value);
         }
     }
@@ -79,43 +203,12 @@ impl<'a> ShiftRegister<'a> {
     
     pub(crate) async fn flush(&mut self) {
         self.reg.write(self.out.get_value()).await; 
+        self.update_output_enable();
     }
-}
-
-#[derive(Debug)]
-pub(crate) struct ShiftRegisterOutputs {
-    outputs: [bool; 16]
-}
-
-fn bitmask_for_index(index: usize) -> u16 {
-    1 << index
-}
 
-impl ShiftRegisterOutputs {
-    pub(crate) fn new() -> Self {
-        let mut outputs = [false; 16];
-
-        ShiftRegisterOutputs {
-            outputs
+    fn update_output_enable(&self) {
+        if let Some(output_enable) = self.output_enable {
+            output_enable.latched();
         }
     }
-    
-    pub(crate) fn set_output(&mut self, index: usize, value: bool) {
-        self.outputs[index] = value;
-    }
-    
-    pub(crate) fn clear(&mut self) {
-        self.outputs = [false; 16];
-    }
-
-    pub(crate) fn get_value(&self) -> u16 {
-        let mut value = 0;
-        for i in 0..16 {
-            if self.outputs[i] {
-                value |= bitmask_for_index(i);
-            }
-        }
-
-        value
-    }
-}
\ No newline at end of file
+}
@@ -1,28 +1,45 @@
 use embassy_rp::gpio::{AnyPin, Output};
 use embassy_time::Timer;
+use crate::iox::binary_output::ShiftRegisterBackend;
+use crate::iox::shift_register_frame::BitOrder;
 
-pub(crate) struct DualC595ShiftRegister<'a> {
+/// N cascaded 74HC595 shift registers, bit-banged over three GPIOs
+pub(crate) struct C595Chain<'a, const N: usize> {
     serial_pin: Output<'a, AnyPin>,
     shift_register_clock_pin: Output<'a, AnyPin>,
     storage_register_clock_pin: Output<'a, AnyPin>,
+    bit_order: BitOrder,
 }
 
-impl<'a> DualC595ShiftRegister<'a> {
+impl<'a, const N: usize> C595Chain<'a, N> {
     pub(crate) fn new(
         serial_pin: Output<'a, AnyPin>,
         shift_register_clock_pin: Output<'a, AnyPin>,
         storage_register_clock_pin: Output<'a, AnyPin>,
     ) -> Self {
-        DualC595ShiftRegister {
+        C595Chain {
             serial_pin,
             shift_register_clock_pin,
             storage_register_clock_pin,
+            bit_order: BitOrder::default(),
         }
     }
 
-    pub(crate) async fn write(&mut self, data: u16) {
-        for i in 0..16 {
-            match data & (1 << i) != 0 {
+    pub(crate) fn bit_order(mut self, bit_order: BitOrder) -> Self {
+        self.bit_order = bit_order;
+        self
+    }
+
+    pub(crate) async fn clear(&mut self) {
+        self.write(&[0; N]).await;
+    }
+}
+
+impl<'a, const N: usize> ShiftRegisterBackend<N> for C595Chain<'a, N> {
+    /// Shifts the whole frame in, then latches it to the outputs
+    async fn write(&mut self, frame: &[u8; N]) {
+        for i in 0..N * 8 {
+            match self.bit_order.bit(frame, i) {
                 true => self.serial_pin.set_high(),
                 false => self.serial_pin.set_low(),
             }
@@ -34,12 +51,12 @@ impl<'a> DualC595ShiftRegister<'a> {
 
         self.serial_pin.set_low();
 
+        self.latch().await;
+    }
+
+    async fn latch(&mut self) {
         self.storage_register_clock_pin.set_high();
         Timer::after_micros(5).await;
         self.storage_register_clock_pin.set_low();
     }
-
-    pub(crate) async fn clear(&mut self) {
-        self.write(0).await;
-    }
-}
\ No newline at end of file
+}
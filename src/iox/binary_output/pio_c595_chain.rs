@@ -0,0 +1,144 @@
+use embassy_rp::clocks::clk_sys_freq;
+use embassy_rp::pio::{Common, Config, Direction, FifoJoin, Instance, Irq, IrqFlags, PioPin, ShiftConfig, ShiftDirection, StateMachine};
+use embassy_time::Timer;
+use fixed::types::U24F8;
+use crate::iox::shift_register_frame::BitOrder;
+use crate::iox::binary_output::ShiftRegisterBackend;
+
+/// PIO clock, every bit takes four cycles so the chain is clocked at 2 MHz
+static PIO_CLOCK_KHZ: u32 = 8_000;
+
+/// N cascaded 74HC595 shift registers clocked out by a PIO state machine
+/// The whole frame goes out in one FIFO word, so at most four registers are supported
+/// The program raises its state machine's IRQ flag once a frame is latched, so irq has to be
+/// the Pio's irq of the same index. Waiting on it needs the PIO's IRQ_0 interrupt bound, e.g.
+/// PIO0_IRQ_0 => pio::InterruptHandler<PIO0> in bind_interrupts!, otherwise write never returns
+pub(crate) struct PioC595Chain<'d, P: Instance, const SM: usize, const N: usize> {
+    sm: StateMachine<'d, P, SM>,
+    irq: Irq<'d, P, SM>,
+    irq_flags: IrqFlags<'d, P>,
+    origin: u8,
+    /// Set from the first push until the frame is confirmed latched, still set if a write was dropped
+    in_flight: bool,
+    bit_order: BitOrder,
+}
+
+impl<'d, P: Instance, const SM: usize, const N: usize> PioC595Chain<'d, P, SM, N> {
+    /// Fails to evaluate, and so the build, for chains that don't fit in one FIFO word
+    const CHAIN_LENGTH_OK: () = assert!(N >= 1 && N <= 4, "a PIO chain holds one to four registers");
+
+    pub(crate) fn new(
+        common: &mut Common<'d, P>,
+        mut sm: StateMachine<'d, P, SM>,
+        irq: Irq<'d, P, SM>,
+        irq_flags: IrqFlags<'d, P>,
+        serial_pin: impl PioPin,
+        shift_register_clock_pin: impl PioPin,
+        storage_register_clock_pin: impl PioPin,
+        bit_order: BitOrder,
+    ) -> Self {
+        let () = Self::CHAIN_LENGTH_OK;
+
+        // Side-set drives the shift clock, set pulses the storage clock after the last bit
+        // Every frame is preceded by its bit count - 1, irq 0 rel flags the latched frame
+        let program = pio_proc::pio_asm!(
+            ".side_set 1",
+            ".wrap_target",
+            "    pull block         side 0",
+            "    mov x, osr         side 0",
+            "    pull block         side 0",
+            "bitloop:",
+            "    out pins, 1        side 0 [1]",
+            "    jmp x-- bitloop    side 1 [1]",
+            "    set pins, 1        side 0 [3]",
+            "    set pins, 0        side 0",
+            "    irq 0 rel          side 0",
+            ".wrap",
+        );
+
+        let serial_pin = common.make_pio_pin(serial_pin);
+        let shift_register_clock_pin = common.make_pio_pin(shift_register_clock_pin);
+        let storage_register_clock_pin = common.make_pio_pin(storage_register_clock_pin);
+        sm.set_pin_dirs(Direction::Out, &[&serial_pin, &shift_register_clock_pin, &storage_register_clock_pin]);
+
+        let loaded = common.load_program(&program.program);
+        let mut cfg = Config::default();
+        cfg.use_program(&loaded, &[&shift_register_clock_pin]);
+        cfg.set_out_pins(&[&serial_pin]);
+        cfg.set_set_pins(&[&storage_register_clock_pin]);
+        cfg.clock_divider = U24F8::from_num(clk_sys_freq() / 1000) / U24F8::from_num(PIO_CLOCK_KHZ);
+        cfg.fifo_join = FifoJoin::TxOnly;
+        cfg.shift_out = ShiftConfig {
+            auto_fill: false,
+            threshold: 32,
+            direction: match bit_order {
+                BitOrder::LsbFirst => ShiftDirection::Right,
+                BitOrder::MsbFirst => ShiftDirection::Left,
+            },
+        };
+
+        sm.set_config(&cfg);
+        sm.set_enable(true);
+
+        PioC595Chain {
+            sm,
+            irq,
+            irq_flags,
+            origin: loaded.origin,
+            in_flight: false,
+            bit_order,
+        }
+    }
+
+    /// Puts the state machine back at the start of the program with empty FIFOs and RCLK low
+    /// if a previous write was dropped, so its leftover words can't mix with the next frame
+    fn recover(&mut self) {
+        if !self.in_flight {
+            return;
+        }
+
+        self.sm.set_enable(false);
+        self.sm.clear_fifos();
+        self.sm.restart();
+        let jmp = pio::InstructionOperands::JMP { condition: pio::JmpCondition::Always, address: self.origin }.encode();
+        let set = pio::InstructionOperands::SET { destination: pio::SetDestination::PINS, data: 0 }.encode();
+        unsafe {
+            self.sm.exec_instr(set);
+            self.sm.exec_instr(jmp);
+        }
+        self.sm.set_enable(true);
+        self.in_flight = false;
+    }
+}
+
+impl<'d, P: Instance, const SM: usize, const N: usize> ShiftRegisterBackend<N> for PioC595Chain<'d, P, SM, N> {
+    /// Returns once the frame is latched, so the outputs can be enabled right after
+    async fn write(&mut self, frame: &[u8; N]) {
+        let word = self.bit_order.frame_word(frame);
+
+        self.recover();
+        // Only this frame's latch may complete the wait below
+        self.irq_flags.clear(SM);
+        self.in_flight = true;
+
+        self.sm.tx().wait_push((N * 8 - 1) as u32).await;
+        self.sm.tx().wait_push(word).await;
+        self.irq.wait().await;
+        self.in_flight = false;
+    }
+
+    /// Forces the storage clock pulse into the state machine while it is stalled on the next pull,
+    /// which it is between calls since write only returns once its frame is latched
+    async fn latch(&mut self) {
+        self.recover();
+
+        let set = |data| pio::InstructionOperands::SET { destination: pio::SetDestination::PINS, data }.encode();
+        unsafe {
+            self.sm.exec_instr(set(1));
+        }
+        Timer::after_micros(1).await;
+        unsafe {
+            self.sm.exec_instr(set(0));
+        }
+    }
+}
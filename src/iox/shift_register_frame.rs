@@ -0,0 +1,139 @@
+// Frame layout shared by the shift register backends, kept free of HAL types so it builds and
+// is tested on the host
+
+/// Order in which the outputs of a frame are clocked into the chain
+/// The first bit clocked in ends up furthest down the chain
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) enum BitOrder {
+    /// Output 0 first
+    #[default]
+    LsbFirst,
+    /// Output 8 * N - 1 first
+    MsbFirst,
+}
+
+impl BitOrder {
+    /// Value of the index-th bit to clock out, frame byte k holds outputs 8k to 8k + 7
+    pub(crate) fn bit<const N: usize>(&self, frame: &[u8; N], index: usize) -> bool {
+        let output = match self {
+            BitOrder::LsbFirst => index,
+            BitOrder::MsbFirst => N * 8 - 1 - index,
+        };
+
+        frame[output / 8] & (1 << (output % 8)) != 0
+    }
+
+    /// Packs a frame of up to four registers into one word so the first bit to clock out sits
+    /// where a 32 bit shift register shifts from, bit 0 for LsbFirst and bit 31 for MsbFirst
+    pub(crate) fn frame_word<const N: usize>(&self, frame: &[u8; N]) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes[..N].copy_from_slice(frame);
+        let word = u32::from_le_bytes(bytes);
+
+        match self {
+            BitOrder::LsbFirst => word,
+            BitOrder::MsbFirst => word << (32 - N * 8),
+        }
+    }
+}
+
+/// Output states of N cascaded registers, byte k holds outputs 8k to 8k + 7
+#[derive(Debug)]
+pub(crate) struct ShiftRegisterOutputs<const N: usize> {
+    outputs: [u8; N]
+}
+
+fn bitmask_for_index(index: usize) -> u8 {
+    1 << (index % 8)
+}
+
+impl<const N: usize> ShiftRegisterOutputs<N> {
+    pub(crate) const OUTPUTS: usize = N * 8;
+
+    pub(crate) fn new() -> Self {
+        ShiftRegisterOutputs {
+            outputs: [0; N]
+        }
+    }
+    
+    pub(crate) fn set_output(&mut self, index: usize, value: bool) {
+        match value {
+            true => self.outputs[index / 8] |= bitmask_for_index(index),
+            false => self.outputs[index / 8] &= !bitmask_for_index(index),
+        }
+    }
+    
+    pub(crate) fn clear(&mut self) {
+        self.outputs = [0; N];
+    }
+
+    pub(crate) fn get_value(&self) -> &[u8; N] {
+        &self.outputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output indices in the order they are clocked into the chain
+    fn clocked_outputs<const N: usize>(order: BitOrder, frame: &[u8; N]) -> Vec<usize> {
+        (0..N * 8).filter(|&i| order.bit(frame, i)).collect()
+    }
+
+    #[test]
+    fn output_index_maps_to_byte_and_bit() {
+        let mut out = ShiftRegisterOutputs::<4>::new();
+        out.set_output(0, true);
+        out.set_output(9, true);
+        out.set_output(31, true);
+        assert_eq!(out.get_value(), &[0x01, 0x02, 0x00, 0x80]);
+
+        out.set_output(9, false);
+        assert_eq!(out.get_value(), &[0x01, 0x00, 0x00, 0x80]);
+        assert_eq!(ShiftRegisterOutputs::<4>::OUTPUTS, 32);
+
+        out.clear();
+        assert_eq!(out.get_value(), &[0; 4]);
+    }
+
+    #[test]
+    fn single_register_bit_order() {
+        let frame = [0b1000_0011];
+        let lsb: Vec<bool> = (0..8).map(|i| BitOrder::LsbFirst.bit(&frame, i)).collect();
+        let msb: Vec<bool> = (0..8).map(|i| BitOrder::MsbFirst.bit(&frame, i)).collect();
+
+        assert_eq!(lsb, [true, true, false, false, false, false, false, true]);
+        assert_eq!(msb, [true, false, false, false, false, false, true, true]);
+    }
+
+    #[test]
+    fn four_register_bit_order() {
+        let mut out = ShiftRegisterOutputs::<4>::new();
+        for output in [0, 8, 17, 31] {
+            out.set_output(output, true);
+        }
+
+        // outputs 0..8 end up in the last register of the chain with LsbFirst, in the first with MsbFirst
+        assert_eq!(clocked_outputs(BitOrder::LsbFirst, out.get_value()), [0, 8, 17, 31]);
+        assert_eq!(clocked_outputs(BitOrder::MsbFirst, out.get_value()), [0, 14, 23, 31]);
+    }
+
+    #[test]
+    fn frame_word_shifts_out_in_bit_order() {
+        fn check<const N: usize>(frame: [u8; N]) {
+            let lsb = BitOrder::LsbFirst.frame_word(&frame);
+            let msb = BitOrder::MsbFirst.frame_word(&frame);
+            for i in 0..N * 8 {
+                assert_eq!(lsb >> i & 1 != 0, BitOrder::LsbFirst.bit(&frame, i));
+                assert_eq!(msb >> (31 - i) & 1 != 0, BitOrder::MsbFirst.bit(&frame, i));
+            }
+        }
+
+        check([0b1000_0011]);
+        check([0x81, 0x42, 0x24, 0x18]);
+        check([0xA5, 0x0F]);
+        assert_eq!(BitOrder::MsbFirst.frame_word(&[0x81]), 0x8100_0000);
+        assert_eq!(BitOrder::LsbFirst.frame_word(&[0x01, 0x02, 0x03, 0x04]), 0x0403_0201);
+    }
+}